@@ -0,0 +1,114 @@
+#ifndef DDSKETCH_H
+#define DDSKETCH_H
+
+/* Declarations of the functions in src/ffi.rs, kept in sync with it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of the fallible C functions
+typedef enum DDSketchStatus {
+  DD_SKETCH_STATUS_OK = 0,
+  // A required pointer argument was null
+  DD_SKETCH_STATUS_NULL_POINTER = 1,
+  // The quantile was not between 0 and 1 (inclusive), see `DDSketchError::Quantile`
+  DD_SKETCH_STATUS_INVALID_QUANTILE = 2,
+  // The sketches have different configs, see `DDSketchError::Merge`
+  DD_SKETCH_STATUS_INCOMPATIBLE_MERGE = 3,
+  // The buffer does not hold an encoded sketch, see `DDSketchError::Decode`
+  DD_SKETCH_STATUS_INVALID_ENCODING = 4,
+  // The sketch holds no samples
+  DD_SKETCH_STATUS_EMPTY = 5,
+  // The buffer is too small for the encoded sketch
+  DD_SKETCH_STATUS_BUFFER_TOO_SMALL = 6,
+  // The function panicked. The sketches it was passed may be left inconsistent and should
+  // only be freed.
+  DD_SKETCH_STATUS_PANIC = 7,
+  // A sketch was passed as both the sketch to change and another argument
+  DD_SKETCH_STATUS_SAME_SKETCH = 8,
+} DDSketchStatus;
+
+// This struct represents a [DDSketch](https://arxiv.org/pdf/1908.10693.pdf)
+typedef struct DDSketch DDSketch;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a sketch, see `Config::new`. Returns null if `alpha` is not between 0 and 1
+// (exclusive), or `max_num_bins` or `min_value` is not positive.
+DDSketch *ddsketch_new(double alpha, uint32_t max_num_bins, double min_value);
+
+// Create a sketch using the default config, see `Config::defaults`
+DDSketch *ddsketch_new_default(void);
+
+// Free a sketch. Does nothing if `sketch` is null.
+//
+// # Safety
+//
+// `sketch` must be null or a sketch that has not been freed yet.
+void ddsketch_free(DDSketch *sketch);
+
+// Add the sample to the sketch
+//
+// # Safety
+//
+// `sketch` must be null or a live sketch.
+DDSketchStatus ddsketch_add(DDSketch *sketch, double v);
+
+// Add the sample to the sketch `count` times
+//
+// # Safety
+//
+// `sketch` must be null or a live sketch.
+DDSketchStatus ddsketch_add_with_count(DDSketch *sketch, double v, uint64_t count);
+
+// Write the quantile `q` of the sketch to `out`. Returns `Empty` if the sketch holds no
+// samples, in which case `out` is left untouched.
+//
+// # Safety
+//
+// `sketch` must be null or a live sketch, and `out` null or valid for writes.
+DDSketchStatus ddsketch_quantile(const DDSketch *sketch, double q, double *out);
+
+// Returns the number of samples in the sketch, or 0 if `sketch` is null
+//
+// # Safety
+//
+// `sketch` must be null or a live sketch.
+uint64_t ddsketch_count(const DDSketch *sketch);
+
+// Merge `other` into `sketch`, leaving `other` unchanged. Returns `SameSketch` if they are
+// the same sketch, which can't be merged into itself.
+//
+// # Safety
+//
+// Both must be null or live sketches.
+DDSketchStatus ddsketch_merge(DDSketch *sketch, const DDSketch *other);
+
+// Encode the sketch into `buf`, see `DDSketch::encode`. The encoded length is written to `len`
+// in any case; if it is larger than `capacity` nothing is written to `buf` and
+// `BufferTooSmall` is returned. `buf` may be null when `capacity` is 0, to query the length.
+//
+// # Safety
+//
+// `sketch` must be null or a live sketch, `buf` valid for `capacity` bytes of writes, and
+// `len` null or valid for writes.
+DDSketchStatus ddsketch_encode(const DDSketch *sketch, uint8_t *buf, size_t capacity, size_t *len);
+
+// Decode a sketch produced by `ddsketch_encode` and store it in `out`, to be freed with
+// `ddsketch_free`.
+//
+// # Safety
+//
+// `buf` must be valid for `len` bytes of reads, and `out` null or valid for writes.
+DDSketchStatus ddsketch_decode(const uint8_t *buf, size_t len, DDSketch **out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DDSKETCH_H */
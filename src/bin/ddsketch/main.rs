@@ -0,0 +1,125 @@
+//! Command line tool for DDSketch.
+//!
+//! Without a subcommand it summarizes numbers read from stdin or files. The `merge`, `inspect`
+//! and `diff` subcommands work on serialized sketches.
+
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+mod sketches;
+mod summary;
+
+/// Error running a command
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid arguments, reported along with the usage of the command
+    Usage(String),
+    /// The command failed
+    Failed(String),
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let command = args.peek().cloned().unwrap_or_default();
+
+    let (result, usage) = match command.as_str() {
+        "merge" => (sketches::merge(args.skip(1)), sketches::MERGE_USAGE),
+        "inspect" => (sketches::inspect(args.skip(1)), sketches::INSPECT_USAGE),
+        "diff" => (sketches::diff(args.skip(1)), sketches::DIFF_USAGE),
+        _ => (summary::run(args), summary::USAGE),
+    };
+
+    match result {
+        Ok(()) => {}
+        Err(Error::Usage(e)) => {
+            eprintln!("ddsketch: {}\n\n{}", e, usage);
+            process::exit(2);
+        }
+        Err(Error::Failed(e)) => {
+            eprintln!("ddsketch: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn parse_number<T: FromStr>(s: &str, name: &str) -> Result<T, String> {
+    s.parse()
+        .map_err(|_| format!("invalid value '{}' for {}", s, name))
+}
+
+// Parse a comma separated list of quantiles
+pub fn parse_quantiles(s: &str, name: &str) -> Result<Vec<f64>, String> {
+    let quantiles = s
+        .split(',')
+        .map(|q| parse_number(q.trim(), name))
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    if quantiles.iter().any(|q| !(0.0..=1.0).contains(q)) {
+        return Err(format!("{} must be between 0 and 1", name));
+    }
+
+    Ok(quantiles)
+}
+
+// Label a quantile as a percentile, e.g. 0.999 as p99.9
+pub fn quantile_label(q: f64) -> String {
+    let percentile = format!("{:.6}", q * 100.0);
+    format!(
+        "p{}",
+        percentile.trim_end_matches('0').trim_end_matches('.')
+    )
+}
+
+// Format rows of cells as left-aligned columns
+pub fn format_table(rows: &[Vec<String>]) -> String {
+    let mut widths = Vec::new();
+    for row in rows {
+        widths.resize(usize::max(widths.len(), row.len()), 0);
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = usize::max(widths[i], cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:<width$}", cell, width = widths[i]))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quantiles() {
+        assert_eq!(parse_quantiles("0.5, 0.99", "-q").unwrap(), vec![0.5, 0.99]);
+        assert!(parse_quantiles("0.5,1.5", "-q").is_err());
+        assert!(parse_quantiles("p50", "-q").is_err());
+    }
+
+    #[test]
+    fn test_quantile_label() {
+        assert_eq!(quantile_label(0.0), "p0");
+        assert_eq!(quantile_label(0.5), "p50");
+        assert_eq!(quantile_label(0.999), "p99.9");
+        assert_eq!(quantile_label(1.0), "p100");
+    }
+
+    #[test]
+    fn test_format_table() {
+        let rows = vec![
+            vec!["a".to_string(), "1".to_string(), "x".to_string()],
+            vec!["bbb".to_string(), "22".to_string()],
+        ];
+        assert_eq!(format_table(&rows), "a    1   x\nbbb  22\n");
+    }
+}
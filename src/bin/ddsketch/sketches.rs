@@ -0,0 +1,358 @@
+//! Subcommands working on serialized sketches.
+//!
+//! Sketches are read and written in the binary form of `DDSketch::encode`, or as JSON when the
+//! file name ends in `.json`.
+
+use std::fs;
+use std::path::Path;
+
+use sketches_ddsketch::{CollapseDirection, DDSketch};
+
+use crate::{format_table, parse_quantiles, quantile_label, Error};
+
+pub const MERGE_USAGE: &str = "\
+Usage: ddsketch merge [OPTIONS] --output <OUTPUT> <SKETCH>...
+
+Merges the sketches, which must share the same configuration, and writes the result to OUTPUT.
+
+Options:
+  -o, --output <OUTPUT>       File to write the merged sketch to
+  -h, --help                  Print this help
+";
+
+pub const INSPECT_USAGE: &str = "\
+Usage: ddsketch inspect [OPTIONS] <SKETCH>
+
+Prints the configuration, counts, collapse status and bins of the sketch.
+
+Options:
+  -h, --help                  Print this help
+";
+
+pub const DIFF_USAGE: &str = "\
+Usage: ddsketch diff [OPTIONS] <A> <B>
+
+Prints the quantiles of both sketches, and how far those of B are from those of A.
+
+Options:
+  -q, --quantiles <Q,...>     Quantiles to compare [default: 0,0.1,0.25,0.5,0.75,0.9,0.95,0.99,0.999,1]
+  -h, --help                  Print this help
+";
+
+const DIFF_QUANTILES: [f64; 10] = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99, 0.999, 1.0];
+
+pub fn merge(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut output = None;
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", MERGE_USAGE);
+                return Ok(());
+            }
+            "-o" | "--output" => output = Some(value(&mut args, &arg)?),
+            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    let output = output.ok_or_else(|| Error::Usage("missing --output".to_string()))?;
+    let mut inputs = inputs.iter();
+    let first = inputs
+        .next()
+        .ok_or_else(|| Error::Usage("no sketches to merge".to_string()))?;
+
+    let mut merged = read_sketch(first)?;
+    for path in inputs {
+        merged
+            .merge(&read_sketch(path)?)
+            .map_err(|e| Error::Failed(format!("{}: {}", path, e)))?;
+    }
+
+    write_sketch(&output, &merged)
+}
+
+pub fn inspect(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", INSPECT_USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    match inputs.as_slice() {
+        [path] => {
+            print!("{}", format_inspect(&read_sketch(path)?));
+            Ok(())
+        }
+        _ => Err(Error::Usage("expected a single sketch".to_string())),
+    }
+}
+
+pub fn diff(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut quantiles = DIFF_QUANTILES.to_vec();
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", DIFF_USAGE);
+                return Ok(());
+            }
+            "-q" | "--quantiles" => {
+                quantiles = parse_quantiles(&value(&mut args, &arg)?, &arg).map_err(Error::Usage)?
+            }
+            _ if arg.starts_with('-') => return Err(unknown_option(&arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    match inputs.as_slice() {
+        [a, b] => {
+            print!(
+                "{}",
+                format_diff(&read_sketch(a)?, &read_sketch(b)?, &quantiles)
+            );
+            Ok(())
+        }
+        _ => Err(Error::Usage("expected two sketches".to_string())),
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::Usage(format!("missing value for {}", name)))
+}
+
+fn unknown_option(arg: &str) -> Error {
+    Error::Usage(format!("unknown option '{}'", arg))
+}
+
+fn is_json(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+fn read_sketch(path: &str) -> Result<DDSketch, Error> {
+    let failed = |e: String| Error::Failed(format!("{}: {}", path, e));
+
+    let buf = fs::read(path).map_err(|e| failed(e.to_string()))?;
+    if is_json(path) {
+        serde_json::from_slice(&buf).map_err(|e| failed(e.to_string()))
+    } else {
+        DDSketch::decode(&buf).map_err(|e| failed(e.to_string()))
+    }
+}
+
+fn write_sketch(path: &str, sketch: &DDSketch) -> Result<(), Error> {
+    let buf = if is_json(path) {
+        serde_json::to_vec(sketch).map_err(|e| Error::Failed(format!("{}: {}", path, e)))?
+    } else {
+        sketch.encode()
+    };
+
+    fs::write(path, buf).map_err(|e| Error::Failed(format!("{}: {}", path, e)))
+}
+
+fn number(v: Option<f64>) -> String {
+    v.map_or("-".to_string(), |v| v.to_string())
+}
+
+fn format_inspect(sketch: &DDSketch) -> String {
+    let config = sketch.config();
+    let positive: Vec<(i32, u64)> = sketch.positive_bins().collect();
+    let negative: Vec<(i32, u64)> = sketch.negative_bins().collect();
+    let collapsed = |count: u64, direction: CollapseDirection| {
+        let direction = match direction {
+            CollapseDirection::Lowest => "lowest",
+            CollapseDirection::Highest => "highest",
+        };
+        format!(
+            "{} ({} bins)",
+            if count > 0 { "yes" } else { "no" },
+            direction
+        )
+    };
+
+    let rows = vec![
+        vec!["alpha".to_string(), config.alpha().to_string()],
+        vec!["gamma".to_string(), config.gamma.to_string()],
+        vec!["max bins".to_string(), config.max_num_bins.to_string()],
+        vec!["min value".to_string(), config.min_possible().to_string()],
+        vec![
+            "zero threshold".to_string(),
+            config.zero_threshold().to_string(),
+        ],
+        vec!["count".to_string(), sketch.count().to_string()],
+        vec!["zero count".to_string(), sketch.zero_count().to_string()],
+        vec!["min".to_string(), number(sketch.min())],
+        vec!["max".to_string(), number(sketch.max())],
+        vec!["sum".to_string(), number(sketch.sum())],
+        vec!["positive bins".to_string(), positive.len().to_string()],
+        vec!["negative bins".to_string(), negative.len().to_string()],
+        vec![
+            "positive collapsed".to_string(),
+            collapsed(
+                sketch.positive_collapsed_count(),
+                config.positive_collapse(),
+            ),
+        ],
+        vec![
+            "negative collapsed".to_string(),
+            collapsed(
+                sketch.negative_collapsed_count(),
+                config.negative_collapse(),
+            ),
+        ],
+        vec![
+            "uniform collapses".to_string(),
+            config.collapses().to_string(),
+        ],
+    ];
+
+    // List the bins in value order
+    let mut bins = vec![vec![
+        "store".to_string(),
+        "key".to_string(),
+        "value".to_string(),
+        "count".to_string(),
+    ]];
+    for (key, count) in negative.iter().rev() {
+        bins.push(vec![
+            "negative".to_string(),
+            key.to_string(),
+            (-config.value(*key)).to_string(),
+            count.to_string(),
+        ]);
+    }
+    if sketch.zero_count() > 0 {
+        bins.push(vec![
+            "zero".to_string(),
+            String::new(),
+            "0".to_string(),
+            sketch.zero_count().to_string(),
+        ]);
+    }
+    for (key, count) in &positive {
+        bins.push(vec![
+            "positive".to_string(),
+            key.to_string(),
+            config.value(*key).to_string(),
+            count.to_string(),
+        ]);
+    }
+
+    format!("{}\n{}", format_table(&rows), format_table(&bins))
+}
+
+fn format_diff(a: &DDSketch, b: &DDSketch, quantiles: &[f64]) -> String {
+    let relative = |a: f64, delta: f64| {
+        if a == 0.0 {
+            "-".to_string()
+        } else {
+            format!("{:+.4}%", delta / a.abs() * 100.0)
+        }
+    };
+
+    let mut rows = vec![
+        vec![
+            String::new(),
+            "a".to_string(),
+            "b".to_string(),
+            "delta".to_string(),
+            "relative".to_string(),
+        ],
+        vec![
+            "count".to_string(),
+            a.count().to_string(),
+            b.count().to_string(),
+            format!("{:+}", b.count() as i128 - a.count() as i128),
+            relative(a.count() as f64, b.count() as f64 - a.count() as f64),
+        ],
+    ];
+
+    for q in quantiles {
+        let qa = a.quantile(*q).unwrap();
+        let qb = b.quantile(*q).unwrap();
+        let (delta, rel) = match (qa, qb) {
+            (Some(qa), Some(qb)) => (format!("{:+}", qb - qa), relative(qa, qb - qa)),
+            _ => ("-".to_string(), "-".to_string()),
+        };
+        rows.push(vec![quantile_label(*q), number(qa), number(qb), delta, rel]);
+    }
+
+    format_table(&rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sketches_ddsketch::Config;
+
+    fn sketch(values: &[f64]) -> DDSketch {
+        let mut sketch = DDSketch::new(Config::new(0.01, 2048, 1e-9));
+        for v in values {
+            sketch.add(*v);
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_inspect() {
+        let out = format_inspect(&sketch(&[-1.0, 0.0, 1.0, 1.0]));
+        assert!(out.contains("count               4\n"));
+        assert!(out.contains("positive bins       1\n"));
+        assert!(out.contains("positive collapsed  no (lowest bins)\n"));
+        assert!(out.contains("negative collapsed  no (lowest bins)\n"));
+        assert!(out.ends_with(
+            "\
+store     key  value                count
+negative  0    -0.9900000000000001  1
+zero           0                    1
+positive  0    0.9900000000000001   2
+"
+        ));
+    }
+
+    #[test]
+    fn test_diff() {
+        let out = format_diff(&sketch(&[1.0, 2.0]), &sketch(&[2.0, 4.0, 6.0]), &[0.0]);
+        assert_eq!(
+            out,
+            "       a  b  delta  relative\ncount  2  3  +1     +50.0000%\np0     1  2  +1     +100.0000%\n"
+        );
+
+        let out = format_diff(&sketch(&[]), &sketch(&[1.0]), &[0.5]);
+        assert!(out.ends_with("p50    -  0.9900000000000001  -      -\n"));
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = std::env::temp_dir();
+        let original = sketch(&[1.0, 2.0, 3.0]);
+
+        for ext in ["bin", "json"] {
+            // Unique to this process, so concurrent test runs don't share the files
+            let name = format!("ddsketch-{}-test_read_write.{}", std::process::id(), ext);
+            let path = dir.join(name);
+            let path = path.to_str().unwrap();
+            write_sketch(path, &original).unwrap();
+
+            let read = read_sketch(path).unwrap();
+            assert_eq!(read.count(), 3);
+            assert_eq!(read.quantile(0.5).unwrap(), original.quantile(0.5).unwrap());
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(matches!(
+            read_sketch("/nonexistent/sketch.bin"),
+            Err(Error::Failed(_))
+        ));
+    }
+}
@@ -0,0 +1,328 @@
+//! Compute a quantile summary of the numbers read from stdin or files.
+//!
+//! Values are read one per line, or from a single column of delimited input such as CSV. Lines
+//! that do not hold a number, such as a CSV header, are skipped.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use serde::{Serialize, Serializer};
+use sketches_ddsketch::{Config, DDSketch};
+
+use crate::{format_table, parse_number, parse_quantiles, quantile_label, Error};
+
+pub const USAGE: &str = "\
+Usage: ddsketch [OPTIONS] [FILE]...
+       ddsketch <merge|inspect|diff> [OPTIONS] <SKETCH>...
+
+Reads numbers from each FILE, or stdin if none are given or FILE is -, and prints their
+count, min, max, sum, mean and quantiles. Run a subcommand with --help to see how it works
+on serialized sketches.
+
+Options:
+  -a, --alpha <ALPHA>         Relative accuracy of the quantiles [default: 0.01]
+  -b, --max-bins <BINS>       Maximum number of bins per store [default: 2048]
+  -q, --quantiles <Q,...>     Quantiles to print [default: 0.5,0.9,0.95,0.99]
+  -c, --column <N>            Read values from the Nth column (starting at 1) of each line
+  -d, --delimiter <CHAR>      Column delimiter [default: ,]
+  -f, --format <text|json>    Output format [default: text]
+  -h, --help                  Print this help
+";
+
+const DEFAULT_ALPHA: f64 = 0.01;
+const DEFAULT_MAX_BINS: u32 = 2048;
+const DEFAULT_MIN_VALUE: f64 = 1.0e-9;
+const DEFAULT_QUANTILES: [f64; 4] = [0.5, 0.9, 0.95, 0.99];
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    alpha: f64,
+    max_bins: u32,
+    quantiles: Vec<f64>,
+    column: Option<usize>,
+    delimiter: char,
+    format: Format,
+    files: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            alpha: DEFAULT_ALPHA,
+            max_bins: DEFAULT_MAX_BINS,
+            quantiles: DEFAULT_QUANTILES.to_vec(),
+            column: None,
+            delimiter: ',',
+            format: Format::Text,
+            files: Vec::new(),
+        }
+    }
+}
+
+pub fn run(args: impl Iterator<Item = String>) -> Result<(), Error> {
+    match parse_args(args).map_err(Error::Usage)? {
+        Some(options) => summarize(&options).map_err(Error::Failed),
+        None => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+    }
+}
+
+fn summarize(options: &Options) -> Result<(), String> {
+    let config = Config::new(options.alpha, options.max_bins, DEFAULT_MIN_VALUE);
+    let mut sketch = DDSketch::new(config);
+
+    let mut skipped = 0;
+    if options.files.is_empty() {
+        skipped += read_values(io::stdin().lock(), options, &mut sketch)
+            .map_err(|e| format!("stdin: {}", e))?;
+    }
+    for path in &options.files {
+        skipped += if path == "-" {
+            read_values(io::stdin().lock(), options, &mut sketch)
+        } else {
+            File::open(path).and_then(|f| read_values(BufReader::new(f), options, &mut sketch))
+        }
+        .map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if skipped > 0 {
+        eprintln!("ddsketch: skipped {} lines without a number", skipped);
+    }
+
+    let output = match options.format {
+        Format::Text => format_text(&sketch, &options.quantiles),
+        Format::Json => format_json(&sketch, &options.quantiles),
+    };
+    print!("{}", output);
+
+    Ok(())
+}
+
+// Returns Ok(None) when help was requested.
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    let mut args = args;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-a" | "--alpha" => {
+                options.alpha = parse_number(&value(&arg)?, &arg)?;
+                if !(options.alpha > 0.0 && options.alpha < 1.0) {
+                    return Err(format!("{} must be between 0 and 1", arg));
+                }
+            }
+            "-b" | "--max-bins" => {
+                options.max_bins = parse_number(&value(&arg)?, &arg)?;
+                if options.max_bins == 0 {
+                    return Err(format!("{} must be at least 1", arg));
+                }
+            }
+            "-q" | "--quantiles" => {
+                options.quantiles = parse_quantiles(&value(&arg)?, &arg)?;
+            }
+            "-c" | "--column" => {
+                let column: usize = parse_number(&value(&arg)?, &arg)?;
+                if column == 0 {
+                    return Err(format!("{} starts at 1", arg));
+                }
+                options.column = Some(column - 1);
+            }
+            "-d" | "--delimiter" => {
+                let delimiter = value(&arg)?;
+                let mut chars = delimiter.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => options.delimiter = c,
+                    _ => return Err(format!("{} must be a single character", arg)),
+                }
+            }
+            "-f" | "--format" => {
+                options.format = match value(&arg)?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    f => return Err(format!("unknown format '{}'", f)),
+                }
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            _ => options.files.push(arg),
+        }
+    }
+
+    Ok(Some(options))
+}
+
+// Add the values read to the sketch, returning the number of lines skipped.
+fn read_values(reader: impl BufRead, options: &Options, sketch: &mut DDSketch) -> io::Result<u64> {
+    let mut skipped = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let field = match options.column {
+            Some(column) => line.split(options.delimiter).nth(column),
+            None => Some(line.as_str()),
+        };
+        match field.and_then(|f| f.trim().parse::<f64>().ok()) {
+            Some(v) if v.is_finite() => sketch.add(v),
+            _ => skipped += 1,
+        }
+    }
+
+    Ok(skipped)
+}
+
+fn format_text(sketch: &DDSketch, quantiles: &[f64]) -> String {
+    let number = |v: Option<f64>| v.map_or("-".to_string(), |v| v.to_string());
+
+    let mut rows = vec![
+        vec!["count".to_string(), sketch.count().to_string()],
+        vec!["min".to_string(), number(sketch.min())],
+        vec!["max".to_string(), number(sketch.max())],
+        vec!["sum".to_string(), number(sketch.sum())],
+        vec!["mean".to_string(), number(sketch.mean())],
+    ];
+    for q in quantiles {
+        rows.push(vec![
+            quantile_label(*q),
+            number(sketch.quantile(*q).unwrap()),
+        ]);
+    }
+
+    format_table(&rows)
+}
+
+// The JSON summary. serde_json writes non-finite numbers, such as a sum that overflowed, as
+// null.
+#[derive(Serialize)]
+struct Summary {
+    count: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: Option<f64>,
+    mean: Option<f64>,
+    #[serde(serialize_with = "serialize_quantiles")]
+    quantiles: Vec<(f64, Option<f64>)>,
+}
+
+// Serialize the quantiles as a map from each quantile to its value, in the order requested
+fn serialize_quantiles<S: Serializer>(
+    quantiles: &[(f64, Option<f64>)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(quantiles.iter().map(|(q, v)| (q.to_string(), v)))
+}
+
+fn format_json(sketch: &DDSketch, quantiles: &[f64]) -> String {
+    let summary = Summary {
+        count: sketch.count(),
+        min: sketch.min(),
+        max: sketch.max(),
+        sum: sketch.sum(),
+        mean: sketch.mean(),
+        quantiles: quantiles
+            .iter()
+            .map(|q| (*q, sketch.quantile(*q).unwrap()))
+            .collect(),
+    };
+
+    serde_json::to_string(&summary).unwrap() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> impl Iterator<Item = String> + '_ {
+        s.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args(args("")).unwrap(), Some(Options::default()));
+        assert_eq!(parse_args(args("-a 0.1 --help")).unwrap(), None);
+
+        let options =
+            parse_args(args("-a 0.05 -b 512 -q 0.1,0.999 -c 2 -d ; -f json a b")).unwrap();
+        assert_eq!(
+            options,
+            Some(Options {
+                alpha: 0.05,
+                max_bins: 512,
+                quantiles: vec![0.1, 0.999],
+                column: Some(1),
+                delimiter: ';',
+                format: Format::Json,
+                files: vec!["a".to_string(), "b".to_string()],
+            })
+        );
+
+        assert!(parse_args(args("-a 2")).is_err());
+        assert!(parse_args(args("-q 0.5,1.5")).is_err());
+        assert!(parse_args(args("-b 0")).is_err());
+        assert!(parse_args(args("-c 0")).is_err());
+        assert!(parse_args(args("-f yaml")).is_err());
+        assert!(parse_args(args("--alpha")).is_err());
+        assert!(parse_args(args("--unknown")).is_err());
+    }
+
+    #[test]
+    fn test_read_values() {
+        let options = Options {
+            column: Some(1),
+            ..Options::default()
+        };
+        let input = "name,latency\na,1.5\nb,2.5\n\nc,n/a\nd\n";
+
+        let mut sketch = DDSketch::default();
+        let skipped = read_values(input.as_bytes(), &options, &mut sketch).unwrap();
+        assert_eq!(sketch.count(), 2);
+        assert_eq!(sketch.sum(), Some(4.0));
+        assert_eq!(skipped, 3);
+    }
+
+    #[test]
+    fn test_format() {
+        let mut sketch = DDSketch::default();
+        sketch.add(1.0);
+        sketch.add(3.0);
+
+        let text = format_text(&sketch, &[1.0]);
+        assert_eq!(
+            text,
+            "count  2\nmin    1\nmax    3\nsum    4\nmean   2\np100   3\n"
+        );
+
+        let json = format_json(&sketch, &[1.0]);
+        assert_eq!(
+            json,
+            "{\"count\":2,\"min\":1.0,\"max\":3.0,\"sum\":4.0,\"mean\":2.0,\"quantiles\":{\"1\":3.0}}\n"
+        );
+        assert!(format_json(&DDSketch::default(), &[]).contains("\"min\":null"));
+
+        // An overflowing sum is not a valid JSON number
+        let mut sketch = DDSketch::default();
+        sketch.add(1.79e308);
+        sketch.add(1.79e308);
+        let json = format_json(&sketch, &[0.5]);
+        assert!(json.contains("\"sum\":null"), "{}", json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["count"], 2);
+        assert_eq!(value["max"], 1.79e308);
+    }
+}
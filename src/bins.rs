@@ -0,0 +1,397 @@
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use crate::config::Config;
+use crate::ddsketch::{Accuracy, DDSketchError, QuantileMethod};
+use crate::store::{collapse_key, Store};
+
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// The bins of a sketch: its config, the stores of positive and negative samples, and the count
+/// of samples too close to zero for either. Sketches tracking different summary statistics
+/// share them, along with the merging, collapsing and quantile logic over them.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+pub(crate) struct Bins {
+    pub(crate) config: Config,
+    pub(crate) store: Store,
+    pub(crate) negative_store: Store,
+    pub(crate) zero_count: u64,
+}
+
+impl Bins {
+    pub(crate) fn new(config: Config) -> Self {
+        Bins {
+            config,
+            store: new_store(&config, false),
+            negative_store: new_store(&config, true),
+            zero_count: 0,
+        }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.store.count() + self.zero_count + self.negative_store.count()
+    }
+
+    pub(crate) fn is_collapsed(&self) -> bool {
+        self.store.is_collapsed() || self.negative_store.is_collapsed()
+    }
+
+    pub(crate) fn length(&self) -> usize {
+        self.store.length() as usize + self.negative_store.length() as usize
+    }
+
+    /// Add `count` samples of value `v` to the bin it maps to, or to the zero count
+    pub(crate) fn add(&mut self, v: f64, count: u64) {
+        if v > self.config.zero_threshold() {
+            self.add_key_count(false, self.config.key(v), count);
+        } else if v < -self.config.zero_threshold() {
+            self.add_key_count(true, self.config.key(-v), count);
+        } else {
+            self.zero_count += count;
+        }
+    }
+
+    /// Add `count` to the bin for `key` of the positive or negative store, collapsing
+    /// uniformly first if needed.
+    pub(crate) fn add_key_count(&mut self, negative: bool, mut key: i32, count: u64) {
+        if self.config.is_uniform() {
+            let range = Some((key, key));
+            let collapses = if negative {
+                self.fit_uniform(None, range)
+            } else {
+                self.fit_uniform(range, None)
+            };
+            for _ in 0..collapses {
+                key = collapse_key(key);
+            }
+        }
+
+        if negative {
+            self.negative_store.add_count(key, count);
+        } else {
+            self.store.add_count(key, count);
+        }
+    }
+
+    /// Add a sample to the bin of each key, growing each store at most once, see
+    /// `Store::add_keys`.
+    pub(crate) fn add_keys(&mut self, positive: &mut [i32], negative: &mut [i32]) {
+        if self.config.is_uniform() {
+            let collapses = self.fit_uniform(key_range(positive), key_range(negative));
+            for key in positive.iter_mut().chain(negative.iter_mut()) {
+                for _ in 0..collapses {
+                    *key = collapse_key(*key);
+                }
+            }
+        }
+        self.store.add_keys(positive);
+        self.negative_store.add_keys(negative);
+    }
+
+    /// Return the value of the sample with the given rank, counting from 0, and whether it is
+    /// within the relative accuracy.
+    pub(crate) fn value_at_rank(&self, rank: u64) -> (f64, Accuracy) {
+        let accuracy = |collapsed: bool| {
+            if collapsed {
+                Accuracy::Collapsed
+            } else {
+                Accuracy::Guaranteed
+            }
+        };
+
+        let negative_count = self.negative_store.count();
+        if rank < negative_count {
+            let reversed_rank = negative_count - rank - 1;
+            let key = self.negative_store.key_at_rank(reversed_rank);
+            (
+                -self.config.value(key),
+                accuracy(self.negative_store.is_collapsed_rank(reversed_rank)),
+            )
+        } else if rank < self.zero_count + negative_count {
+            (0.0, Accuracy::Guaranteed)
+        } else {
+            let rank = rank - self.zero_count - negative_count;
+            let key = self.store.key_at_rank(rank);
+            (
+                self.config.value(key),
+                accuracy(self.store.is_collapsed_rank(rank)),
+            )
+        }
+    }
+
+    /// Return the quantile `q` of the samples, see `quantile_by_method`. The bins must not be
+    /// empty.
+    pub(crate) fn quantile(&self, q: f64, (min, max): (f64, f64), method: QuantileMethod) -> f64 {
+        quantile_by_method(q, self.count(), (min, max), method, |rank| {
+            self.value_at_rank(rank).0
+        })
+    }
+
+    /// Whether `o` can be merged into these bins, see `merge`
+    pub(crate) fn can_merge(&self, o: &Bins) -> bool {
+        if self.config.is_uniform() {
+            self.config.is_compatible(&o.config)
+        } else {
+            same_but_zero_threshold(&self.config, &o.config)
+        }
+    }
+
+    /// Merge the bins of `o` into these. Uniformly collapsing bins are first collapsed to the
+    /// same gamma, and further if the merged bins would exceed the budget. Bins whose zero
+    /// thresholds differ are both raised to the larger one.
+    pub(crate) fn merge(&mut self, o: &Bins) -> Result<()> {
+        let mut o = Cow::Borrowed(o);
+        if self.config.is_uniform() {
+            if !self.config.is_compatible(&o.config) {
+                return Err(DDSketchError::Merge);
+            }
+
+            while self.config.collapses() < o.config.collapses() {
+                self.collapse_uniform();
+            }
+            while o.config.collapses() < self.config.collapses() {
+                o.to_mut().collapse_uniform();
+            }
+            for _ in 0..self.fit_uniform(o.store.key_range(), o.negative_store.key_range()) {
+                o.to_mut().collapse_uniform();
+            }
+        }
+
+        if !same_but_zero_threshold(&self.config, &o.config) {
+            return Err(DDSketchError::Merge);
+        }
+        let threshold = f64::max(self.config.zero_threshold(), o.config.zero_threshold());
+        self.raise_zero_threshold(threshold);
+        if o.config.zero_threshold() < threshold {
+            o.to_mut().raise_zero_threshold(threshold);
+        }
+
+        self.store.merge(&o.store);
+        self.negative_store.merge(&o.negative_store);
+        self.zero_count += o.zero_count;
+
+        Ok(())
+    }
+
+    /// Merge the bins of all of `others` into these, with the same result as merging them one
+    /// at a time. Nothing is merged if any of them can't be.
+    ///
+    /// Bins with the same config are merged in a single pass, growing the stores once. Others,
+    /// and uniformly collapsing ones, may need collapsing or raising the zero threshold between
+    /// merges, so are merged one at a time.
+    pub(crate) fn merge_all(&mut self, others: &[&Bins]) -> Result<()> {
+        if !others.iter().all(|o| self.can_merge(o)) {
+            return Err(DDSketchError::Merge);
+        }
+
+        if self.config.is_uniform() || others.iter().any(|o| o.config != self.config) {
+            for o in others {
+                self.merge(o)?;
+            }
+            return Ok(());
+        }
+
+        let stores: Vec<&Store> = others.iter().map(|o| &o.store).collect();
+        let negative_stores: Vec<&Store> = others.iter().map(|o| &o.negative_store).collect();
+        self.store.merge_all(&stores);
+        self.negative_store.merge_all(&negative_stores);
+        self.zero_count += others.iter().map(|o| o.zero_count).sum::<u64>();
+
+        Ok(())
+    }
+
+    /// Fold every `factor` adjacent bins into one, so that the bin for key `k` covers the bins
+    /// for keys `factor * (k - 1) + 1` to `factor * k`, giving the bins of `config`. Its gamma
+    /// must be this one raised to `factor`, see `Config::fold_factor`.
+    pub(crate) fn fold(&mut self, factor: u32, config: Config) {
+        self.config = config;
+        self.store.fold(factor as i32);
+        self.negative_store.fold(factor as i32);
+    }
+
+    /// Raise the zero threshold of the config to `threshold` if lower, moving the bins entirely
+    /// within it into the zero count.
+    pub(crate) fn raise_zero_threshold(&mut self, threshold: f64) {
+        if threshold <= self.config.zero_threshold() {
+            return;
+        }
+
+        self.config = self.config.with_zero_threshold(threshold);
+        let mut key = self.config.key(threshold);
+        if self.config.pow_gamma(key) > threshold {
+            key -= 1;
+        }
+        self.zero_count += self.store.remove_up_to(key) + self.negative_store.remove_up_to(key);
+    }
+
+    pub(crate) fn collapse_uniform(&mut self) {
+        self.config.collapse();
+        self.store.collapse_uniform();
+        self.negative_store.collapse_uniform();
+    }
+
+    /// Collapse uniformly until the stores, extended to also cover the given key ranges, fit
+    /// in the bin budget, then split the budget between the stores so neither collapses its
+    /// lowest bins. Returns the number of collapses, so callers can remap their keys.
+    pub(crate) fn fit_uniform(
+        &mut self,
+        positive: Option<(i32, i32)>,
+        negative: Option<(i32, i32)>,
+    ) -> u32 {
+        let max_num_bins = self.config.max_num_bins as usize;
+        let mut positive = union(self.store.key_range(), positive);
+        let mut negative = union(self.negative_store.key_range(), negative);
+
+        let mut collapses = 0;
+        while span(positive) + span(negative) > max_num_bins {
+            let collapsed = (collapse_range(positive), collapse_range(negative));
+            if collapsed == (positive, negative) {
+                break;
+            }
+
+            self.collapse_uniform();
+            positive = collapsed.0;
+            negative = collapsed.1;
+            collapses += 1;
+        }
+
+        // The keys fit, but the bins may have been allocated in larger chunks.
+        let positive_len = usize::max(self.store.length() as usize, span(positive));
+        let negative_len = usize::max(self.negative_store.length() as usize, span(negative));
+        if positive_len + negative_len > max_num_bins {
+            self.store.shrink_to_fit();
+            self.negative_store.shrink_to_fit();
+        }
+
+        // Empty stores keep a limit of one bin, as stores never have a limit of zero
+        let negative_len = usize::max(self.negative_store.length() as usize, span(negative));
+        self.negative_store
+            .set_bin_limit(usize::max(negative_len, 1));
+        self.store.set_bin_limit(usize::max(
+            max_num_bins.saturating_sub(negative_len),
+            usize::max(span(positive), 1),
+        ));
+
+        collapses
+    }
+}
+
+// Return an empty store of negative or positive samples, collapsing as set in the config
+fn new_store(config: &Config, negative: bool) -> Store {
+    let (direction, bin_limit) = config.store_limit(negative);
+    Store::new(bin_limit).with_direction(direction)
+}
+
+// Whether the configs only differ by their zero thresholds, if at all
+pub(crate) fn same_but_zero_threshold(a: &Config, b: &Config) -> bool {
+    a.with_zero_threshold(0.0) == b.with_zero_threshold(0.0)
+}
+
+// Return the quantile `q`, between 0 and 1, of `count` samples, picking their ranks with
+// `method`. The exact `min` and `max` are returned for 0 and 1.
+pub(crate) fn quantile_by_method(
+    q: f64,
+    count: u64,
+    (min, max): (f64, f64),
+    method: QuantileMethod,
+    value_at_rank: impl Fn(u64) -> f64,
+) -> f64 {
+    if q == 0.0 {
+        return min;
+    } else if q == 1.0 {
+        return max;
+    }
+
+    let max_rank = count - 1;
+    let rank = q * max_rank as f64;
+    let lower = rank as u64;
+    let fraction = rank - lower as f64;
+    let upper = u64::min(lower + (fraction > 0.0) as u64, max_rank);
+
+    match method {
+        QuantileMethod::Lower => value_at_rank(lower),
+        QuantileMethod::Upper => value_at_rank(upper),
+        QuantileMethod::Nearest => {
+            // Round half to even, like numpy
+            if fraction > 0.5 || (fraction == 0.5 && lower % 2 == 1) {
+                value_at_rank(upper)
+            } else {
+                value_at_rank(lower)
+            }
+        }
+        QuantileMethod::Linear => {
+            let lower = value_at_rank(lower);
+            let upper = value_at_rank(upper);
+            lower + fraction * (upper - lower)
+        }
+    }
+}
+
+fn key_range(keys: &[i32]) -> Option<(i32, i32)> {
+    Some((*keys.iter().min()?, *keys.iter().max()?))
+}
+
+fn union(a: Option<(i32, i32)>, b: Option<(i32, i32)>) -> Option<(i32, i32)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((i32::min(a.0, b.0), i32::max(a.1, b.1))),
+        _ => a.or(b),
+    }
+}
+
+fn span(range: Option<(i32, i32)>) -> usize {
+    range.map_or(0, |(min_key, max_key)| (max_key - min_key + 1) as usize)
+}
+
+fn collapse_range(range: Option<(i32, i32)>) -> Option<(i32, i32)> {
+    range.map(|(min_key, max_key)| (collapse_key(min_key), collapse_key(max_key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bins;
+    use crate::Config;
+
+    #[test]
+    fn test_merge_all_same_as_merge() {
+        let config = Config::new(0.01, 128, 1e-9);
+        let mut others = Vec::new();
+        for (i, threshold) in [1e-9, 1e-9, 1.0].iter().enumerate() {
+            let mut bins = Bins::new(config.with_zero_threshold(*threshold));
+            for v in 0..100 {
+                bins.add((v as f64 - 30.0) * (i + 1) as f64 / 10.0, 1);
+            }
+            others.push(bins);
+        }
+        let others: Vec<&Bins> = others.iter().collect();
+
+        let mut merged = Bins::new(config);
+        for o in &others {
+            merged.merge(o).unwrap();
+        }
+        let mut merged_all = Bins::new(config);
+        merged_all.merge_all(&others).unwrap();
+        assert_eq!(merged_all.config, merged.config);
+        assert_eq!(merged_all.config.zero_threshold(), 1.0);
+        assert_eq!(merged_all.zero_count, merged.zero_count);
+        assert!(merged_all.store.bins().eq(merged.store.bins()));
+        assert!(merged_all
+            .negative_store
+            .bins()
+            .eq(merged.negative_store.bins()));
+        assert_eq!(merged_all.count(), 300);
+
+        // Same config: single pass over the stores
+        let mut merged = Bins::new(config);
+        merged.merge_all(&others[..2]).unwrap();
+        assert_eq!(merged.count(), 200);
+
+        let uniform = Bins::new(Config::uniform_collapsing(0.01, 128, 1e-9));
+        assert!(!merged.can_merge(&uniform));
+        assert!(merged.merge_all(&[others[0], &uniform]).is_err());
+        assert_eq!(merged.count(), 200);
+    }
+}
@@ -0,0 +1,361 @@
+//! Distances and two-sample tests between the distributions of two sketches, for example a
+//! baseline and a canary.
+//!
+//! Both sketches must have compatible configs, so their bins line up. Each bin stands for the
+//! value it maps back to, which bounds the error of the distances by the relative accuracy of
+//! the sketches.
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::ddsketch::{DDSketch, DDSketchError};
+use crate::math;
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// Outcome of a two-sample test, see `DDSketch::ks_test` and `DDSketch::mann_whitney_test`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TestResult {
+    /// The test statistic
+    pub statistic: f64,
+    /// The probability of a statistic at least as extreme if both sketches summarize samples
+    /// of the same distribution
+    pub p_value: f64,
+}
+
+impl DDSketch {
+    /// Returns the Kolmogorov–Smirnov statistic, the largest distance between the cumulative
+    /// distributions of both sketches, or None if either is empty. Fails if the configs are not
+    /// compatible.
+    pub fn ks_statistic(&self, o: &DDSketch) -> Result<Option<f64>> {
+        let (a, b) = self.aligned(o)?;
+        let (a, b) = (value_bins(&a), value_bins(&b));
+        if a.is_empty() || b.is_empty() {
+            return Ok(None);
+        }
+
+        let (n_a, n_b) = (total(&a) as f64, total(&b) as f64);
+        let mut d: f64 = 0.0;
+        for (_, cum_a, cum_b) in cumulative(&a, &b) {
+            d = d.max((cum_a as f64 / n_a - cum_b as f64 / n_b).abs());
+        }
+
+        Ok(Some(d))
+    }
+
+    /// Returns the Wasserstein-1 (earth mover's) distance between the distributions of both
+    /// sketches, in the unit of the values, or None if either is empty. Fails if the configs are
+    /// not compatible.
+    pub fn wasserstein_distance(&self, o: &DDSketch) -> Result<Option<f64>> {
+        let (a, b) = self.aligned(o)?;
+        let (a, b) = (value_bins(&a), value_bins(&b));
+        if a.is_empty() || b.is_empty() {
+            return Ok(None);
+        }
+
+        // Integrate the distance between the cumulative distributions, a step function
+        let (n_a, n_b) = (total(&a) as f64, total(&b) as f64);
+        let mut distance = 0.0;
+        let mut prev: Option<(f64, f64)> = None;
+        for (v, cum_a, cum_b) in cumulative(&a, &b) {
+            if let Some((prev_v, prev_d)) = prev {
+                distance += prev_d * (v - prev_v);
+            }
+            prev = Some((v, (cum_a as f64 / n_a - cum_b as f64 / n_b).abs()));
+        }
+
+        Ok(Some(distance))
+    }
+
+    /// Returns the largest relative difference between the quantiles of both sketches over all
+    /// quantiles, or None if either is empty. The difference between values `a` and `b` is
+    /// `|a - b| / max(|a|, |b|)`, so it lies between 0 and 2. Fails if the configs are not
+    /// compatible.
+    pub fn max_relative_quantile_difference(&self, o: &DDSketch) -> Result<Option<f64>> {
+        let (a, b) = self.aligned(o)?;
+        let (a, b) = (value_bins(&a), value_bins(&b));
+        if a.is_empty() || b.is_empty() {
+            return Ok(None);
+        }
+
+        // Both quantile functions are step functions, so walk the steps of either in turn.
+        // Comparing cross products keeps the ranks exact.
+        let (n_a, n_b) = (total(&a) as u128, total(&b) as u128);
+        let (mut i, mut j) = (0, 0);
+        let (mut cum_a, mut cum_b) = (a[0].1 as u128, b[0].1 as u128);
+        let mut max_diff: f64 = 0.0;
+        loop {
+            max_diff = max_diff.max(relative_difference(a[i].0, b[j].0));
+
+            let ord = (cum_a * n_b).cmp(&(cum_b * n_a));
+            if ord != Ordering::Greater {
+                i += 1;
+                if i == a.len() {
+                    break;
+                }
+                cum_a += a[i].1 as u128;
+            }
+            if ord != Ordering::Less {
+                j += 1;
+                if j == b.len() {
+                    break;
+                }
+                cum_b += b[j].1 as u128;
+            }
+        }
+
+        Ok(Some(max_diff))
+    }
+
+    /// Kolmogorov–Smirnov two-sample test, with the sample counts of the sketches as sample
+    /// sizes. The statistic is `ks_statistic`, and the p-value comes from the asymptotic
+    /// Kolmogorov distribution, which is accurate once both sketches hold more than a few dozen
+    /// samples. Returns None if either sketch is empty, and fails if the configs are not
+    /// compatible.
+    pub fn ks_test(&self, o: &DDSketch) -> Result<Option<TestResult>> {
+        let d = match self.ks_statistic(o)? {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+
+        // Numerical Recipes' correction for small samples
+        let (n_a, n_b) = (self.count() as f64, o.count() as f64);
+        let en = math::sqrt(n_a * n_b / (n_a + n_b));
+        let lambda = (en + 0.12 + 0.11 / en) * d;
+
+        Ok(Some(TestResult {
+            statistic: d,
+            p_value: kolmogorov_q(lambda),
+        }))
+    }
+
+    /// Two-sided Mann–Whitney U test. The statistic is U for this sketch, the number of pairs
+    /// in which its sample is smaller than the other's, counting samples in the same bin as
+    /// ties worth a half. The p-value comes from the normal approximation with tie correction,
+    /// which is accurate once both sketches hold more than a few dozen samples. Returns None
+    /// if either sketch is empty, and fails if the configs are not compatible.
+    pub fn mann_whitney_test(&self, o: &DDSketch) -> Result<Option<TestResult>> {
+        let (a, b) = self.aligned(o)?;
+        let (a, b) = (value_bins(&a), value_bins(&b));
+        if a.is_empty() || b.is_empty() {
+            return Ok(None);
+        }
+
+        let (n_a, n_b) = (total(&a) as f64, total(&b) as f64);
+        let mut u = 0.0;
+        let mut ties = 0.0;
+        let (mut prev_a, mut prev_b) = (0, 0);
+        for (_, cum_a, cum_b) in cumulative(&a, &b) {
+            let (count_a, count_b) = ((cum_a - prev_a) as f64, (cum_b - prev_b) as f64);
+            u += count_a * ((n_b - cum_b as f64) + count_b / 2.0);
+            let t = count_a + count_b;
+            ties += t * t * t - t;
+            prev_a = cum_a;
+            prev_b = cum_b;
+        }
+
+        let n = n_a + n_b;
+        let variance = n_a * n_b / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+        let p_value = if variance > 0.0 {
+            let z = (u - n_a * n_b / 2.0) / math::sqrt(variance);
+            math::erfc(z.abs() / core::f64::consts::SQRT_2)
+        } else {
+            1.0
+        };
+
+        Ok(Some(TestResult {
+            statistic: u,
+            p_value: p_value.min(1.0),
+        }))
+    }
+}
+
+/// Returns the non-empty bins of the sketch as (value, count), in increasing value order
+pub(crate) fn value_bins(s: &DDSketch) -> Vec<(f64, u64)> {
+    let config = s.config();
+    let mut bins: Vec<(f64, u64)> = s
+        .negative_bins()
+        .map(|(key, count)| (-config.value(key), count))
+        .collect();
+    bins.reverse();
+    if s.zero_count() > 0 {
+        bins.push((0.0, s.zero_count()));
+    }
+    bins.extend(
+        s.positive_bins()
+            .map(|(key, count)| (config.value(key), count)),
+    );
+
+    bins
+}
+
+pub(crate) fn total(bins: &[(f64, u64)]) -> u64 {
+    bins.iter().map(|(_, count)| count).sum()
+}
+
+/// Walks the distinct values of both bin lists in increasing order, yielding each value with
+/// the cumulative counts of both up to and including it.
+pub(crate) fn cumulative<'a>(
+    a: &'a [(f64, u64)],
+    b: &'a [(f64, u64)],
+) -> impl Iterator<Item = (f64, u64, u64)> + 'a {
+    let (mut i, mut j) = (0, 0);
+    let (mut cum_a, mut cum_b) = (0, 0);
+    core::iter::from_fn(move || {
+        let v = match (a.get(i), b.get(j)) {
+            (Some(x), Some(y)) => f64::min(x.0, y.0),
+            (Some(x), None) => x.0,
+            (None, Some(y)) => y.0,
+            (None, None) => return None,
+        };
+        if a.get(i).is_some_and(|x| x.0 == v) {
+            cum_a += a[i].1;
+            i += 1;
+        }
+        if b.get(j).is_some_and(|y| y.0 == v) {
+            cum_b += b[j].1;
+            j += 1;
+        }
+
+        Some((v, cum_a, cum_b))
+    })
+}
+
+// Survival function of the Kolmogorov distribution, Q(λ) = 2 Σ (-1)^(j-1) exp(-2 j² λ²)
+fn kolmogorov_q(lambda: f64) -> f64 {
+    // The series converges slowly for small λ, where Q is 1 to double precision
+    if lambda < 0.2 {
+        return 1.0;
+    }
+
+    let mut sum = 0.0;
+    let mut sign = 2.0;
+    for j in 1..=100 {
+        let term = sign * math::exp(-2.0 * (j * j) as f64 * lambda * lambda);
+        sum += term;
+        if term.abs() <= 1e-10 * sum {
+            break;
+        }
+        sign = -sign;
+    }
+
+    f64::clamp(sum, 0.0, 1.0)
+}
+
+fn relative_difference(a: f64, b: f64) -> f64 {
+    let scale = f64::max(a.abs(), b.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (a - b).abs() / scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use approx::assert_relative_eq;
+
+    use crate::test_util::sketch;
+    use crate::{Config, DDSketch};
+
+    #[test]
+    fn test_distances() {
+        let a = sketch(&[1.0, 2.0, 3.0, 4.0]);
+        let b = sketch(&[3.0, 4.0, 5.0, 6.0]);
+
+        assert_eq!(a.ks_statistic(&a).unwrap(), Some(0.0));
+        assert_eq!(a.wasserstein_distance(&a).unwrap(), Some(0.0));
+        assert_eq!(a.max_relative_quantile_difference(&a).unwrap(), Some(0.0));
+
+        assert_eq!(a.ks_statistic(&b).unwrap(), Some(0.5));
+        assert_relative_eq!(
+            a.wasserstein_distance(&b).unwrap().unwrap(),
+            2.0,
+            max_relative = 0.02
+        );
+        assert_relative_eq!(
+            a.max_relative_quantile_difference(&b).unwrap().unwrap(),
+            2.0 / 3.0,
+            max_relative = 0.02
+        );
+        assert_eq!(b.ks_statistic(&a).unwrap(), a.ks_statistic(&b).unwrap());
+
+        // Negative values and zeros are walked in value order
+        let c = sketch(&[-2.0, 0.0, 0.0, 2.0]);
+        let d = sketch(&[-1.0, 0.0, 1.0, 3.0]);
+        assert_eq!(c.ks_statistic(&d).unwrap(), Some(0.25));
+        assert_relative_eq!(
+            c.wasserstein_distance(&d).unwrap().unwrap(),
+            0.75,
+            max_relative = 0.02
+        );
+        assert_relative_eq!(
+            c.max_relative_quantile_difference(&d).unwrap().unwrap(),
+            1.0,
+            max_relative = 0.02
+        );
+    }
+
+    #[test]
+    fn test_two_sample_tests() {
+        let geometric = |from: i32, to: i32| {
+            let values: Vec<f64> = (from..to).map(|i| 1.1f64.powi(i)).collect();
+            sketch(&values)
+        };
+        let a = geometric(0, 100);
+        let b = geometric(20, 120);
+
+        let same = a.ks_test(&a).unwrap().unwrap();
+        assert_eq!(same.statistic, 0.0);
+        assert_eq!(same.p_value, 1.0);
+
+        let ks = a.ks_test(&b).unwrap().unwrap();
+        assert_relative_eq!(ks.statistic, 0.2);
+        assert_relative_eq!(ks.p_value, 0.031376652, max_relative = 1e-6);
+
+        let same = a.mann_whitney_test(&a).unwrap().unwrap();
+        assert_eq!(same.statistic, 5000.0);
+        assert_eq!(same.p_value, 1.0);
+
+        let mw = a.mann_whitney_test(&b).unwrap().unwrap();
+        assert_eq!(mw.statistic, 6800.0);
+        assert_relative_eq!(mw.p_value, 1.0913631e-5, max_relative = 1e-4);
+        let mw = b.mann_whitney_test(&a).unwrap().unwrap();
+        assert_eq!(mw.statistic, 3200.0);
+
+        // All samples tied
+        let c = sketch(&[1.0, 1.0]);
+        assert_eq!(c.mann_whitney_test(&c).unwrap().unwrap().p_value, 1.0);
+        assert_eq!(a.ks_test(&sketch(&[])).unwrap(), None);
+        assert_eq!(a.mann_whitney_test(&sketch(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let a = sketch(&[1.0]);
+        assert_eq!(a.ks_statistic(&sketch(&[])).unwrap(), None);
+        assert_eq!(a.wasserstein_distance(&sketch(&[])).unwrap(), None);
+
+        let other = DDSketch::new(Config::new(0.02, 2048, 1e-9));
+        assert!(a.ks_statistic(&other).is_err());
+        assert!(a.max_relative_quantile_difference(&other).is_err());
+    }
+
+    #[test]
+    fn test_uniform_collapses() {
+        let config = Config::uniform_collapsing(0.01, 64, 1e-9);
+        let mut a = DDSketch::new(config);
+        let mut b = DDSketch::new(config);
+        for i in 1..=100 {
+            a.add(i as f64);
+            b.add((i * 1000) as f64);
+        }
+        b.add(1e-6);
+        assert!(a.config().collapses() != b.config().collapses());
+
+        assert_eq!(a.ks_statistic(&a).unwrap(), Some(0.0));
+        assert!(a.ks_statistic(&b).unwrap().unwrap() > 0.9);
+    }
+}
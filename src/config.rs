@@ -1,3 +1,12 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::ddsketch::DDSketchError;
+use crate::encoding::{self, Reader};
+use crate::mapping::{self, KeyMapping};
+use crate::math;
+use crate::store::CollapseDirection;
+
 #[cfg(feature = "use_serde")]
 use serde::{Deserialize, Serialize};
 
@@ -5,6 +14,10 @@ const DEFAULT_MAX_BINS: u32 = 2048;
 const DEFAULT_ALPHA: f64 = 0.01;
 const DEFAULT_MIN_VALUE: f64 = 1.0e-9;
 
+// Uniform collapsing can not merge the bins on either side of 1.0, so each store needs at
+// least two bins.
+const MIN_UNIFORM_BINS: u32 = 4;
+
 /// The configuration struct for constructing a `DDSketch`
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
@@ -14,10 +27,28 @@ pub struct Config {
     gamma_ln: f64,
     min_value: f64,
     pub offset: i32,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    uniform: bool,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    collapses: u32,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    mapping: KeyMapping,
+    // Largest absolute value counted as zero, or 0.0 to use `min_value`
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    zero_threshold: f64,
+    // How each store collapses, at a bin limit of `max_num_bins` when 0
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    positive_collapse: CollapseDirection,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    positive_max_num_bins: u32,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    negative_collapse: CollapseDirection,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    negative_max_num_bins: u32,
 }
 
 fn log_gamma(value: f64, gamma_ln: f64) -> f64 {
-    value.ln() / gamma_ln
+    math::ln(value) / gamma_ln
 }
 
 impl Config {
@@ -27,7 +58,7 @@ impl Config {
     /// `max_num_bins` is the max number of bins the DDSketch will grow to, in steps of 128 bins.
     pub fn new(alpha: f64, max_num_bins: u32, min_value: f64) -> Self {
         let gamma_ln = (2.0 * alpha) / (1.0 - alpha);
-        let gamma_ln = gamma_ln.ln_1p();
+        let gamma_ln = math::ln_1p(gamma_ln);
 
         Config {
             max_num_bins,
@@ -35,33 +66,293 @@ impl Config {
             gamma_ln,
             min_value,
             offset: 1 - (log_gamma(min_value, gamma_ln) as i32),
+            uniform: false,
+            collapses: 0,
+            mapping: KeyMapping::Logarithmic,
+            zero_threshold: 0.0,
+            positive_collapse: CollapseDirection::Lowest,
+            positive_max_num_bins: 0,
+            negative_collapse: CollapseDirection::Lowest,
+            negative_max_num_bins: 0,
+        }
+    }
+
+    /// Return the config with values mapped to keys by `mapping`. The relative accuracy stays
+    /// the same, `KeyMapping::CubicInterpolated` narrowing the bins where needed to keep it.
+    pub fn with_mapping(mut self, mapping: KeyMapping) -> Self {
+        self.mapping = mapping;
+        self.offset = 1 - (self.log_gamma(self.min_value) as i32);
+        self
+    }
+
+    /// Return the config with samples of absolute value at most `threshold` counted as zero
+    /// rather than mapped to a bin, such as to ignore noise. Unlike `min_value`, this leaves the
+    /// keys unchanged. Thresholds below `min_value` have no effect.
+    ///
+    /// Merging sketches whose thresholds differ raises both to the larger threshold, moving the
+    /// bins entirely within it into the zero count.
+    pub fn with_zero_threshold(mut self, threshold: f64) -> Self {
+        self.zero_threshold = if threshold > self.min_value {
+            threshold
+        } else {
+            0.0
+        };
+        self
+    }
+
+    /// Return the config with the store of positive samples collapsing its bins in `direction`
+    /// once it holds `max_num_bins` of them, rather than collapsing its lowest bins at the
+    /// `max_num_bins` of the config. Uniformly collapsing configs bound both stores together
+    /// and ignore this.
+    pub fn with_positive_collapse(
+        mut self,
+        direction: CollapseDirection,
+        max_num_bins: u32,
+    ) -> Self {
+        self.positive_collapse = direction;
+        self.positive_max_num_bins = max_num_bins;
+        self
+    }
+
+    /// Like `with_positive_collapse`, for the store of negative samples. Its keys are those of
+    /// the absolute values, so `CollapseDirection::Highest` keeps the accuracy of the negative
+    /// samples closest to zero, giving up that of the most negative ones instead.
+    pub fn with_negative_collapse(
+        mut self,
+        direction: CollapseDirection,
+        max_num_bins: u32,
+    ) -> Self {
+        self.negative_collapse = direction;
+        self.negative_max_num_bins = max_num_bins;
+        self
+    }
+
+    /// Construct a `Config` that collapses bins uniformly, as in
+    /// [UDDSketch](https://arxiv.org/pdf/2004.08604.pdf).
+    ///
+    /// `max_num_bins` bounds the bins of both stores together. Rather than collapsing the lowest
+    /// bins once it is reached, the sketch merges each pair of adjacent bins and squares gamma.
+    /// The relative error stays the same across all quantiles, but grows with every collapse;
+    /// `alpha` returns the current guarantee.
+    pub fn uniform_collapsing(alpha: f64, max_num_bins: u32, min_value: f64) -> Self {
+        Config {
+            uniform: true,
+            ..Self::new(alpha, max_num_bins.max(MIN_UNIFORM_BINS), min_value)
         }
     }
 
+    /// Construct a `Config` whose sketches keep the memory used by their bins within
+    /// `max_bytes`, collapsing uniformly as with `uniform_collapsing`.
+    ///
+    /// Each bin takes 8 bytes, and bins are allocated without slack, so the budget covers
+    /// `max_bytes / 8` bins. The fixed size of the sketch itself is not counted. Uniform
+    /// collapsing needs at least 4 bins, so budgets below 32 bytes can't be honored and are
+    /// raised to 32 bytes.
+    pub fn with_max_bytes(alpha: f64, max_bytes: usize, min_value: f64) -> Self {
+        let max_num_bins = (max_bytes / mem::size_of::<u64>()).min(u32::MAX as usize) as u32;
+        Self::uniform_collapsing(alpha, max_num_bins, min_value)
+    }
+
     /// Return a `Config` using built-in default settings
     pub fn defaults() -> Self {
         Self::new(DEFAULT_ALPHA, DEFAULT_MAX_BINS, DEFAULT_MIN_VALUE)
     }
 
     pub fn key(&self, v: f64) -> i32 {
-        self.log_gamma(v).ceil() as i32
+        math::ceil(self.log_gamma(v)) as i32
     }
 
     pub fn value(&self, key: i32) -> f64 {
-        self.pow_gamma(key) * (2.0 / (1.0 + self.gamma))
+        match self.mapping {
+            KeyMapping::Logarithmic => self.pow_gamma(key) * (2.0 / (1.0 + self.gamma)),
+            KeyMapping::CubicInterpolated => {
+                // The value with the same relative distance to both bounds of the bin
+                let lower = self.pow_gamma(key - 1);
+                let upper = self.pow_gamma(key);
+                2.0 * lower * upper / (lower + upper)
+            }
+        }
     }
 
     pub fn log_gamma(&self, value: f64) -> f64 {
-        log_gamma(value, self.gamma_ln)
+        match self.mapping {
+            KeyMapping::Logarithmic => log_gamma(value, self.gamma_ln),
+            KeyMapping::CubicInterpolated => {
+                mapping::approx_log2(value) / (mapping::MIN_SLOPE * self.gamma_ln)
+            }
+        }
     }
 
     pub fn pow_gamma(&self, key: i32) -> f64 {
-        ((key as f64) * self.gamma_ln).exp()
+        match self.mapping {
+            KeyMapping::Logarithmic => math::exp((key as f64) * self.gamma_ln),
+            KeyMapping::CubicInterpolated => {
+                mapping::approx_exp2((key as f64) * mapping::MIN_SLOPE * self.gamma_ln)
+            }
+        }
+    }
+
+    /// Return how values are mapped to keys
+    pub fn mapping(&self) -> KeyMapping {
+        self.mapping
     }
 
     pub fn min_possible(&self) -> f64 {
         self.min_value
     }
+
+    /// Return the largest absolute value counted as zero, see `with_zero_threshold`
+    pub fn zero_threshold(&self) -> f64 {
+        f64::max(self.zero_threshold, self.min_value)
+    }
+
+    /// Return the relative accuracy guaranteed by the current gamma. This is the `alpha` the
+    /// config was constructed with, unless bins have since been collapsed uniformly.
+    pub fn alpha(&self) -> f64 {
+        (self.gamma - 1.0) / (self.gamma + 1.0)
+    }
+
+    /// Return how the store of negative or positive samples collapses its bins, and its bin
+    /// limit.
+    pub(crate) fn store_limit(&self, negative: bool) -> (CollapseDirection, usize) {
+        let (direction, max_num_bins) = if negative {
+            (self.negative_collapse, self.negative_max_num_bins)
+        } else {
+            (self.positive_collapse, self.positive_max_num_bins)
+        };
+
+        match max_num_bins {
+            0 => (direction, self.max_num_bins as usize),
+            max_num_bins => (direction, max_num_bins as usize),
+        }
+    }
+
+    /// Whether `max_num_bins` bounds both stores together and is enforced by collapsing
+    /// uniformly, rather than bounding each store by collapsing its lowest bins.
+    pub(crate) fn is_uniform(&self) -> bool {
+        self.uniform
+    }
+
+    /// Square gamma, so that the bin for key `k` covers the bins for keys `2k - 1` and `2k`.
+    pub(crate) fn collapse(&mut self) {
+        self.gamma *= self.gamma;
+        self.gamma_ln *= 2.0;
+        self.offset = 1 - (self.log_gamma(self.min_value) as i32);
+        self.collapses += 1;
+    }
+
+    /// Return the number of bins of this config that fold exactly into each bin of a config
+    /// with relative accuracy `alpha`, or None if its gamma is not an integer power of this one.
+    pub(crate) fn fold_factor(&self, alpha: f64) -> Option<u32> {
+        let factor = math::ln_1p((2.0 * alpha) / (1.0 - alpha)) / self.gamma_ln;
+        let rounded = math::ceil(factor - 0.5);
+        if rounded >= 2.0 && rounded <= u32::MAX as f64 && (factor - rounded).abs() <= 1e-9 * factor
+        {
+            Some(rounded as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Return a config like this one, but with relative accuracy `alpha`
+    pub(crate) fn with_alpha(&self, alpha: f64) -> Config {
+        Config {
+            uniform: self.uniform,
+            zero_threshold: self.zero_threshold,
+            positive_collapse: self.positive_collapse,
+            positive_max_num_bins: self.positive_max_num_bins,
+            negative_collapse: self.negative_collapse,
+            negative_max_num_bins: self.negative_max_num_bins,
+            ..Self::new(alpha, self.max_num_bins, self.min_value)
+        }
+        .with_mapping(self.mapping)
+    }
+
+    /// Whether sketches with these configs can be merged once collapsed to the same gamma.
+    pub(crate) fn is_compatible(&self, o: &Config) -> bool {
+        // Doubling the log of gamma is exact, so the original value can be recovered.
+        let base_gamma_ln = |c: &Config| c.gamma_ln / math::powi(2.0, c.collapses as i32);
+
+        self.max_num_bins == o.max_num_bins
+            && self.min_value == o.min_value
+            && self.uniform == o.uniform
+            && self.mapping == o.mapping
+            && self.store_limit(false) == o.store_limit(false)
+            && self.store_limit(true) == o.store_limit(true)
+            && base_gamma_ln(self) == base_gamma_ln(o)
+    }
+
+    /// Return the direction the store of positive samples collapses its bins in
+    pub fn positive_collapse(&self) -> CollapseDirection {
+        self.positive_collapse
+    }
+
+    /// Return the direction the store of negative samples collapses its bins in
+    pub fn negative_collapse(&self) -> CollapseDirection {
+        self.negative_collapse
+    }
+
+    /// Return the number of times bins have been collapsed uniformly, each squaring gamma
+    pub fn collapses(&self) -> u32 {
+        self.collapses
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        encoding::put_u32(buf, self.max_num_bins);
+        encoding::put_f64(buf, self.gamma);
+        encoding::put_f64(buf, self.gamma_ln);
+        encoding::put_f64(buf, self.min_value);
+        encoding::put_i32(buf, self.offset);
+        encoding::put_u8(buf, self.uniform as u8);
+        encoding::put_u32(buf, self.collapses);
+        encoding::put_u8(buf, self.mapping as u8);
+        encoding::put_f64(buf, self.zero_threshold);
+        encoding::put_u8(buf, self.positive_collapse as u8);
+        encoding::put_u32(buf, self.positive_max_num_bins);
+        encoding::put_u8(buf, self.negative_collapse as u8);
+        encoding::put_u32(buf, self.negative_max_num_bins);
+    }
+
+    pub(crate) fn decode(r: &mut Reader) -> Result<Self, DDSketchError> {
+        let config = Config {
+            max_num_bins: r.u32()?,
+            gamma: r.f64()?,
+            gamma_ln: r.f64()?,
+            min_value: r.f64()?,
+            offset: r.i32()?,
+            uniform: r.bool()?,
+            collapses: r.u32()?,
+            mapping: match r.u8()? {
+                0 => KeyMapping::Logarithmic,
+                1 => KeyMapping::CubicInterpolated,
+                _ => return Err(DDSketchError::Decode),
+            },
+            zero_threshold: r.f64()?,
+            positive_collapse: decode_direction(r)?,
+            positive_max_num_bins: r.u32()?,
+            negative_collapse: decode_direction(r)?,
+            negative_max_num_bins: r.u32()?,
+        };
+
+        // Reject configs that would map values to nonsensical keys
+        if !(config.gamma > 1.0
+            && (math::ln_1p(config.gamma - 1.0) / config.gamma_ln - 1.0).abs() <= 1e-6
+            && config.min_value >= 0.0
+            && config.zero_threshold >= 0.0)
+        {
+            return Err(DDSketchError::Decode);
+        }
+
+        Ok(config)
+    }
+}
+
+fn decode_direction(r: &mut Reader) -> Result<CollapseDirection, DDSketchError> {
+    match r.u8()? {
+        0 => Ok(CollapseDirection::Lowest),
+        1 => Ok(CollapseDirection::Highest),
+        _ => Err(DDSketchError::Decode),
+    }
 }
 
 impl Default for Config {
@@ -1,21 +1,32 @@
-use std::error;
-use std::fmt;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::time::Duration;
 
+use crate::bins::{same_but_zero_threshold, Bins};
 use crate::config::Config;
-use crate::store::Store;
+use crate::encoding::{self, Reader};
+use crate::math;
+use crate::moments::Moments;
+use crate::store::{collapse_key, Store, MAX_KEY};
 
 #[cfg(feature = "use_serde")]
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-type Result<T> = std::result::Result<T, DDSketchError>;
+type Result<T> = core::result::Result<T, DDSketchError>;
 
-/// General error type for DDSketch, represents either an invalid quantile or an
-/// incompatible merge operation.
+const ENCODING_MAGIC: &[u8] = b"DDSK";
+const ENCODING_VERSION: u8 = 1;
+
+/// General error type for DDSketch, represents either an invalid quantile, an
+/// incompatible merge operation or an invalid encoded sketch.
 ///
 #[derive(Debug, Clone)]
 pub enum DDSketchError {
     Quantile,
     Merge,
+    Decode,
 }
 impl fmt::Display for DDSketchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -24,20 +35,85 @@ impl fmt::Display for DDSketchError {
                 write!(f, "Invalid quantile, must be between 0 and 1 (inclusive)")
             }
             DDSketchError::Merge => write!(f, "Can not merge sketches with different configs"),
+            DDSketchError::Decode => write!(f, "Invalid encoded sketch"),
         }
     }
 }
-impl error::Error for DDSketchError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+#[cfg(feature = "std")]
+impl std::error::Error for DDSketchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         // Generic
         None
     }
 }
 
+/// Whether a quantile returned by `DDSketch::quantile_with_guarantee` is within the relative
+/// accuracy of the sketch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Accuracy {
+    /// The quantile is within the relative accuracy `alpha` of the sketch
+    Guaranteed,
+    /// The rank of the quantile falls in a bin holding samples collapsed to stay within
+    /// `max_num_bins`, so the quantile may be arbitrarily far off, towards zero unless the store
+    /// collapses its highest bins, see `Config::with_positive_collapse`
+    Collapsed,
+}
+
+/// How `DDSketch::quantile_with_method` picks the sample for a quantile `q` when its rank
+/// `q * (count - 1)` falls between two samples. These match the methods of the same name of
+/// numpy's `quantile`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum QuantileMethod {
+    /// The lower of the two samples, as `DDSketch::quantile` does
+    #[default]
+    Lower,
+    /// The upper of the two samples
+    Upper,
+    /// The nearest of the two samples, or the one with an even rank if halfway between them
+    Nearest,
+    /// Interpolate linearly between the values of the two samples
+    Linear,
+}
+
 /// This struct represents a [DDSketch](https://arxiv.org/pdf/1908.10693.pdf)
 #[derive(Clone)]
-#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 pub struct DDSketch {
+    bins: Bins,
+    min: f64,
+    max: f64,
+    sum: f64,
+    moments: Moments,
+    // Rounding error lost from `sum`, see `neumaier_add`
+    sum_compensation: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+// The serialized fields of a sketch, kept flat as they were before the bins were shared with
+// `LightDDSketch`.
+#[cfg(feature = "use_serde")]
+#[derive(Serialize)]
+#[serde(rename = "DDSketch")]
+struct SerializeDDSketch<'a> {
+    config: &'a Config,
+    store: &'a Store,
+    negative_store: &'a Store,
+    min: f64,
+    max: f64,
+    sum: f64,
+    zero_count: u64,
+    moments: &'a Moments,
+    sum_compensation: f64,
+}
+
+#[cfg(feature = "use_serde")]
+#[derive(Deserialize)]
+#[serde(rename = "DDSketch")]
+struct DeserializeDDSketch {
     config: Config,
     store: Store,
     negative_store: Store,
@@ -45,11 +121,47 @@ pub struct DDSketch {
     max: f64,
     sum: f64,
     zero_count: u64,
+    #[serde(default)]
+    moments: Moments,
+    #[serde(default)]
+    sum_compensation: f64,
 }
 
-impl Default for DDSketch {
-    fn default() -> Self {
-        Self::new(Default::default())
+#[cfg(feature = "use_serde")]
+impl Serialize for DDSketch {
+    fn serialize<S: Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+        SerializeDDSketch {
+            config: &self.bins.config,
+            store: &self.bins.store,
+            negative_store: &self.bins.negative_store,
+            min: self.min,
+            max: self.max,
+            sum: self.sum,
+            zero_count: self.bins.zero_count,
+            moments: &self.moments,
+            sum_compensation: self.sum_compensation,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl<'de> Deserialize<'de> for DDSketch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> core::result::Result<Self, D::Error> {
+        let sketch = DeserializeDDSketch::deserialize(deserializer)?;
+        Ok(DDSketch {
+            bins: Bins {
+                config: sketch.config,
+                store: sketch.store,
+                negative_store: sketch.negative_store,
+                zero_count: sketch.zero_count,
+            },
+            min: sketch.min,
+            max: sketch.max,
+            sum: sketch.sum,
+            moments: sketch.moments,
+            sum_compensation: sketch.sum_compensation,
+        })
     }
 }
 
@@ -58,35 +170,161 @@ impl DDSketch {
     /// Construct a `DDSketch`. Requires a `Config` specifying the parameters of the sketch
     pub fn new(config: Config) -> Self {
         DDSketch {
-            config,
-            store: Store::new(config.max_num_bins as usize),
-            negative_store: Store::new(config.max_num_bins as usize),
+            bins: Bins::new(config),
             min: f64::INFINITY,
             max: f64::NEG_INFINITY,
             sum: 0.0,
-            zero_count: 0,
+            moments: Moments::default(),
+            sum_compensation: 0.0,
         }
     }
 
     /// Add the sample to the sketch
     pub fn add(&mut self, v: f64) {
-        if v > self.config.min_possible() {
-            let key = self.config.key(v);
-            self.store.add(key);
-        } else if v < -self.config.min_possible() {
-            let key = self.config.key(-v);
-            self.negative_store.add(key);
-        } else {
-            self.zero_count += 1;
+        self.add_with_count(v, 1);
+    }
+
+    /// Add the sample to the sketch `count` times
+    pub fn add_with_count(&mut self, v: f64, count: u64) {
+        if count == 0 {
+            return;
         }
+        self.add_summary(v, count);
+        self.bins.add(v, count);
+    }
 
-        if v < self.min {
-            self.min = v;
+    /// Add `count` samples to the bin for `key` of positive samples, for keys already computed
+    /// with the mapping of this config, such as bins decoded from another system. As the values
+    /// are unknown, `min`, `max`, `sum` and the moments are updated as if the samples all had
+    /// the value of the bin.
+    ///
+    /// Keys beyond ±2^29, the range of keys a store can hold, are clamped to it, so the samples
+    /// land in the edge bin.
+    pub fn add_positive_key(&mut self, key: i32, count: u64) {
+        if count == 0 {
+            return;
         }
-        if self.max < v {
-            self.max = v;
+        let key = key.clamp(-MAX_KEY, MAX_KEY);
+        self.add_summary(self.bins.config.value(key), count);
+        self.bins.add_key_count(false, key, count);
+    }
+
+    /// Add `count` samples to the bin for `key` of negative samples, keyed by their absolute
+    /// values, see `add_positive_key`.
+    pub fn add_negative_key(&mut self, key: i32, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let key = key.clamp(-MAX_KEY, MAX_KEY);
+        self.add_summary(-self.bins.config.value(key), count);
+        self.bins.add_key_count(true, key, count);
+    }
+
+    /// Add `count` samples too close to zero to be mapped to a bin, as if they were all 0.0
+    pub fn add_zero_count(&mut self, count: u64) {
+        if count == 0 {
+            return;
+        }
+        self.add_summary(0.0, count);
+        self.bins.zero_count += count;
+    }
+
+    /// Construct a `DDSketch` from the keys and counts of its bins of positive and negative
+    /// samples, as returned by `positive_bins` and `negative_bins`, and its count of samples
+    /// too close to zero. `min`, `max`, `sum` and the moments are estimated from the bins, and
+    /// out of range keys clamped, see `add_positive_key`.
+    pub fn from_bins<P, N>(config: Config, positive: P, negative: N, zero_count: u64) -> Self
+    where
+        P: IntoIterator<Item = (i32, u64)>,
+        N: IntoIterator<Item = (i32, u64)>,
+    {
+        let mut sketch = DDSketch::new(config);
+        for (key, count) in positive {
+            sketch.add_positive_key(key, count);
+        }
+        for (key, count) in negative {
+            sketch.add_negative_key(key, count);
+        }
+        sketch.add_zero_count(zero_count);
+        sketch
+    }
+
+    /// Construct a `DDSketch` from its bins, see `from_bins`, along with the exact `min`, `max`
+    /// and `sum` of its samples. Only the moments are estimated from the bins, so `mean` may
+    /// differ slightly from `sum` divided by the count.
+    pub fn from_bins_with_summary<P, N>(
+        config: Config,
+        positive: P,
+        negative: N,
+        zero_count: u64,
+        min: f64,
+        max: f64,
+        sum: f64,
+    ) -> Self
+    where
+        P: IntoIterator<Item = (i32, u64)>,
+        N: IntoIterator<Item = (i32, u64)>,
+    {
+        let mut sketch = DDSketch::from_bins(config, positive, negative, zero_count);
+        sketch.min = min;
+        sketch.max = max;
+        sketch.sum = sum;
+        sketch.sum_compensation = 0.0;
+        sketch
+    }
+
+    /// Add all the samples to the sketch, with the same result as adding them one at a time.
+    /// Keys are computed first, so each store grows at most once, rather than its range being
+    /// checked for every sample.
+    pub fn add_slice(&mut self, values: &[f64]) {
+        let config = self.bins.config;
+        let zero_threshold = config.zero_threshold();
+        let mut positive = Vec::new();
+        let mut negative = Vec::new();
+        for v in values {
+            self.moments.add(*v, 1);
+
+            if *v > zero_threshold {
+                positive.push(config.key(*v));
+            } else if *v < -zero_threshold {
+                negative.push(config.key(-*v));
+            } else {
+                self.bins.zero_count += 1;
+            }
+
+            if *v < self.min {
+                self.min = *v;
+            }
+            if self.max < *v {
+                self.max = *v;
+            }
+            neumaier_add(&mut self.sum, &mut self.sum_compensation, *v);
         }
-        self.sum += v;
+
+        self.bins.add_keys(&mut positive, &mut negative);
+    }
+
+    /// Add an `f32` sample to the sketch
+    pub fn add_f32(&mut self, v: f32) {
+        self.add(v as f64);
+    }
+
+    /// Add an unsigned integer sample, such as a counter, to the sketch. Integers beyond 2^53 lose
+    /// precision, well within the accuracy of the sketch.
+    pub fn add_u64(&mut self, v: u64) {
+        self.add(v as f64);
+    }
+
+    /// Add a signed integer sample to the sketch. Integers beyond 2^53 in magnitude lose
+    /// precision, well within the accuracy of the sketch.
+    pub fn add_i64(&mut self, v: i64) {
+        self.add(v as f64);
+    }
+
+    /// Add a duration to the sketch, recorded in seconds. Use the `_duration` query methods to
+    /// read durations back out.
+    pub fn add_duration(&mut self, v: Duration) {
+        self.add(v.as_secs_f64());
     }
 
     /// Return the quantile value for quantiles between 0.0 and 1.0. Result is an error, represented
@@ -94,7 +332,13 @@ impl DDSketch {
     ///
     /// If the sketch is empty the result is None, else Some(v) for the quantile value.
     pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
-        if q < 0.0 || q > 1.0 {
+        Ok(self.quantile_with_guarantee(q)?.map(|(v, _)| v))
+    }
+
+    /// Like `quantile`, but also returns whether the relative accuracy guarantee holds for the
+    /// quantile, which is not the case when its rank falls in a bin holding collapsed samples.
+    pub fn quantile_with_guarantee(&self, q: f64) -> Result<Option<(f64, Accuracy)>> {
+        if !(0.0..=1.0).contains(&q) {
             return Err(DDSketchError::Quantile);
         }
 
@@ -103,27 +347,64 @@ impl DDSketch {
         }
 
         if q == 0.0 {
-            return Ok(Some(self.min));
+            return Ok(Some((self.min, Accuracy::Guaranteed)));
         } else if q == 1.0 {
-            return Ok(Some(self.max));
+            return Ok(Some((self.max, Accuracy::Guaranteed)));
         }
 
         let rank = (q * (self.count() as f64 - 1.0)) as u64;
-        let quantile;
-        if rank < self.negative_store.count() {
-            let reversed_rank = self.negative_store.count() - rank - 1;
-            let key = self.negative_store.key_at_rank(reversed_rank);
-            quantile = -self.config.value(key);
-        } else if rank < self.zero_count + self.negative_store.count() {
-            quantile = 0.0;
+        Ok(Some(self.bins.value_at_rank(rank)))
+    }
+
+    /// Like `quantile`, but picks the rank of the quantile with `method`, as numpy's `quantile`
+    /// does. `QuantileMethod::Lower` matches `quantile`.
+    pub fn quantile_with_method(&self, q: f64, method: QuantileMethod) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        if self.empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.bins.quantile(q, (self.min, self.max), method)))
+    }
+
+    /// Returns the number of samples at most `v`, counting every sample in the bin `v` falls
+    /// in, so it is only as accurate as the bins are.
+    pub fn rank(&self, v: f64) -> u64 {
+        let Bins {
+            config,
+            store,
+            negative_store,
+            zero_count,
+        } = &self.bins;
+        let zero_threshold = config.zero_threshold();
+        let below_zero = negative_store.count() + zero_count;
+        if v > zero_threshold {
+            let key = config.key(v);
+            below_zero
+                + store
+                    .bins()
+                    .take_while(|(k, _)| *k <= key)
+                    .map(|(_, count)| count)
+                    .sum::<u64>()
+        } else if v < -zero_threshold {
+            let key = config.key(-v);
+            negative_store
+                .bins()
+                .filter(|(k, _)| *k >= key)
+                .map(|(_, count)| count)
+                .sum()
         } else {
-            let key = self
-                .store
-                .key_at_rank(rank - self.zero_count - self.negative_store.count());
-            quantile = self.config.value(key);
+            below_zero
         }
+    }
 
-        Ok(Some(quantile))
+    /// Return the quantile of a sketch holding durations added with `add_duration`. Negative
+    /// values are clamped to zero, and values past `Duration::MAX` to it.
+    pub fn quantile_duration(&self, q: f64) -> Result<Option<Duration>> {
+        Ok(self.quantile(q)?.map(to_duration))
     }
 
     /// Returns the minimum value seen, or None if sketch is empty
@@ -144,46 +425,383 @@ impl DDSketch {
         }
     }
 
-    /// Returns the sum of values seen, or None if sketch is empty
+    /// Returns the minimum duration seen, or None if sketch is empty
+    pub fn min_duration(&self) -> Option<Duration> {
+        self.min().map(to_duration)
+    }
+
+    /// Returns the maximum duration seen, or None if sketch is empty
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max().map(to_duration)
+    }
+
+    /// Returns the sum of values seen, or None if sketch is empty. The sum is compensated for
+    /// rounding errors, so it stays accurate over many small values and merges.
     pub fn sum(&self) -> Option<f64> {
         if self.empty() {
             None
         } else {
-            Some(self.sum)
+            Some(self.sum + self.sum_compensation)
         }
     }
 
+    /// Returns the mean of values seen, or None if sketch is empty. Like `sum`, this is exact
+    /// rather than estimated from the bins, and computed from the compensated sum.
+    pub fn mean(&self) -> Option<f64> {
+        self.sum().map(|sum| sum / self.count() as f64)
+    }
+
+    /// Returns the population variance of values seen, or None if sketch is empty. This is
+    /// exact, tracked as samples are added rather than estimated from the bins. Also None for
+    /// sketches deserialized from before it was tracked, or merged with one.
+    pub fn variance(&self) -> Option<f64> {
+        self.moments().map(Moments::variance)
+    }
+
+    /// Returns the population standard deviation of values seen, or None if sketch is empty.
+    /// This is exact, see `variance`.
+    pub fn stddev(&self) -> Option<f64> {
+        self.variance().map(math::sqrt)
+    }
+
+    /// Returns the population skewness of values seen, or None if sketch is empty. This is
+    /// exact, see `variance`, and 0.0 if all values are equal.
+    pub fn skewness(&self) -> Option<f64> {
+        self.moments().map(Moments::skewness)
+    }
+
     /// Returns the number of values added to the sketch
     pub fn count(&self) -> usize {
-        (self.store.count() + self.zero_count + self.negative_store.count()) as usize
+        self.bins.count() as usize
+    }
+
+    /// Returns the configuration of the sketch. Uniformly collapsing sketches update it as they
+    /// collapse.
+    pub fn config(&self) -> &Config {
+        &self.bins.config
+    }
+
+    /// Returns the number of samples too close to zero to be mapped to a bin
+    pub fn zero_count(&self) -> u64 {
+        self.bins.zero_count
+    }
+
+    /// Returns an iterator over the keys and counts of the non-empty bins holding positive
+    /// samples, in key order.
+    pub fn positive_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.bins.store.bins()
+    }
+
+    /// Returns an iterator over the keys and counts of the non-empty bins holding negative
+    /// samples, in key order. Keys are those of the absolute values.
+    pub fn negative_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.bins.negative_store.bins()
+    }
+
+    /// Returns true if either store has collapsed its bins to stay within its bin limit
+    pub fn is_collapsed(&self) -> bool {
+        self.bins.is_collapsed()
+    }
+
+    /// Returns the number of positive samples in the bin the positive bins collapsed into,
+    /// whose values are only known to be at most that of the bin, or at least when collapsing
+    /// the highest bins. 0 if none collapsed.
+    pub fn positive_collapsed_count(&self) -> u64 {
+        self.bins.store.collapsed_count()
+    }
+
+    /// Returns the number of negative samples in the bin the negative bins collapsed into, whose
+    /// values are only known to be at least that of the bin, or at most when collapsing the
+    /// highest bins, those of the most negative samples. 0 if none collapsed.
+    pub fn negative_collapsed_count(&self) -> u64 {
+        self.bins.negative_store.collapsed_count()
+    }
+
+    /// Returns the relative accuracy currently guaranteed for every quantile. This only changes
+    /// from the configured `alpha` once a uniformly collapsing sketch has collapsed its bins.
+    pub fn alpha(&self) -> f64 {
+        self.bins.config.alpha()
     }
 
     /// Returns the length of the underlying `Store`. This is mainly only useful for understanding
     /// how much the sketch has grown given the inserted values.
     pub fn length(&self) -> usize {
-        self.store.length() as usize + self.negative_store.length() as usize
+        self.bins.length()
     }
 
     /// Merge the contents of another sketch into this one. The sketch that is merged into this one
     /// is unchanged after the merge.
+    ///
+    /// Uniformly collapsing sketches are first collapsed to the same gamma, and further
+    /// if the merged bins would exceed the budget. Sketches whose zero thresholds differ are
+    /// both raised to the larger one, see `Config::with_zero_threshold`.
     pub fn merge(&mut self, o: &DDSketch) -> Result<()> {
-        if self.config != o.config {
+        let was_empty = self.empty();
+        self.bins.merge(&o.bins)?;
+        self.merge_summary(o, was_empty);
+
+        Ok(())
+    }
+
+    /// Merge the contents of all the given sketches into this one, with the same result as
+    /// merging them one at a time. The key range of all the sketches is computed first, so the
+    /// bins are grown once and summed in a single pass. Nothing is merged if any of the
+    /// sketches can't be merged into this one, see `merge`.
+    ///
+    /// Uniformly collapsing sketches may need collapsing between merges, and sketches with
+    /// differing zero thresholds moving bins into the zero count, so they are merged one at a
+    /// time.
+    pub fn merge_all<'a, I>(&mut self, sketches: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a DDSketch>,
+    {
+        let sketches: Vec<&DDSketch> = sketches.into_iter().collect();
+        let bins: Vec<&Bins> = sketches.iter().map(|o| &o.bins).collect();
+
+        let mut was_empty = self.empty();
+        self.bins.merge_all(&bins)?;
+        for o in sketches {
+            self.merge_summary(o, was_empty);
+            was_empty &= o.empty();
+        }
+
+        Ok(())
+    }
+
+    /// Returns a copy of the sketch using another config, such as one with a different `alpha`.
+    /// Each bin is re-bucketed into the bins of the new config it overlaps, splitting its count
+    /// in proportion to the overlap, as if its values were spread evenly across it. The total
+    /// count is kept exactly, as are `min`, `max`, `sum` and the moments.
+    ///
+    /// Quantiles of the result are only guaranteed to within `g * (1 + b) - 1`, for `g` the
+    /// gamma of the current config, `(1 + a) / (1 - a)` for accuracy `a`, and `b` the accuracy
+    /// of `config`, so roughly `2a + b`: a sample may have been anywhere in its bin, so its
+    /// share of the count can end up in a new bin at the other end of it.
+    pub fn convert_to(&self, config: &Config) -> DDSketch {
+        if self.bins.config == *config {
+            return self.clone();
+        }
+
+        let mut out = DDSketch::new(*config);
+        out.min = self.min;
+        out.max = self.max;
+        out.sum = self.sum;
+        out.sum_compensation = self.sum_compensation;
+        out.moments = self.moments;
+        out.bins.zero_count = self.bins.zero_count;
+
+        // Uniform collapses while adding remap the keys of the new config
+        let base_collapses = out.bins.config.collapses();
+        let mut pieces = Vec::new();
+        for (negative, bins) in [
+            (false, self.bins.store.bins()),
+            (true, self.bins.negative_store.bins()),
+        ] {
+            for (key, count) in bins {
+                split_bin(&self.bins.config, config, key, count, |key, count| {
+                    pieces.push((negative, key, count))
+                });
+            }
+        }
+        for (negative, mut key, count) in pieces {
+            let config = &out.bins.config;
+            for _ in base_collapses..config.collapses() {
+                key = collapse_key(key);
+            }
+            if config.pow_gamma(key) <= config.zero_threshold() {
+                out.bins.zero_count += count;
+                continue;
+            }
+            out.bins.add_key_count(negative, key, count);
+        }
+
+        out
+    }
+
+    /// Returns a copy of the sketch with the coarser relative accuracy `alpha`, using fewer bins.
+    /// The count, `min`, `max`, `sum` and the moments are kept exactly. Returns an unchanged
+    /// copy if `alpha` is not larger than the current accuracy.
+    ///
+    /// When the new gamma is an integer power `m` of the current one, as for `alpha` of
+    /// `(g^m - 1) / (g^m + 1)` with `g` the current gamma, every `m` adjacent bins are folded
+    /// into one and the result is as if the samples had been added with the new accuracy.
+    /// Otherwise the bins are re-bucketed with `convert_to`, with the accuracy described there.
+    pub fn downsample(&self, alpha: f64) -> DDSketch {
+        if !(alpha > self.alpha() && alpha < 1.0) {
+            return self.clone();
+        }
+
+        match self.bins.config.fold_factor(alpha) {
+            Some(factor) => {
+                // Built from `alpha` rather than by raising gamma to `factor`, which would differ
+                // in the last bits, so the result merges with sketches of that config
+                let mut out = self.clone();
+                out.bins.fold(factor, self.bins.config.with_alpha(alpha));
+                out
+            }
+            None => self.convert_to(&self.bins.config.with_alpha(alpha)),
+        }
+    }
+
+    /// Merge another sketch into this one, first converting it to this config with
+    /// `convert_to` if the configs differ. The merged sketch is only as accurate as described
+    /// there.
+    pub fn merge_lossy(&mut self, o: &DDSketch) -> Result<()> {
+        if self.bins.can_merge(&o.bins) || same_but_zero_threshold(self.config(), o.config()) {
+            return self.merge(o);
+        }
+
+        self.merge(&o.convert_to(self.config()))
+    }
+
+    /// Returns both sketches at the same uniform collapse level and zero threshold, cloning
+    /// whichever needs changing, or an error if their configs are not compatible.
+    pub(crate) fn aligned<'a>(
+        &'a self,
+        o: &'a DDSketch,
+    ) -> Result<(Cow<'a, DDSketch>, Cow<'a, DDSketch>)> {
+        let mut a = Cow::Borrowed(self);
+        let mut b = Cow::Borrowed(o);
+        if self.config().is_uniform() && self.config().is_compatible(o.config()) {
+            while a.config().collapses() < b.config().collapses() {
+                a.to_mut().bins.collapse_uniform();
+            }
+            while b.config().collapses() < a.config().collapses() {
+                b.to_mut().bins.collapse_uniform();
+            }
+        }
+
+        if !same_but_zero_threshold(a.config(), b.config()) {
             return Err(DDSketchError::Merge);
         }
+        let threshold = f64::max(a.config().zero_threshold(), b.config().zero_threshold());
+        for sketch in [&mut a, &mut b] {
+            if sketch.config().zero_threshold() < threshold {
+                sketch.to_mut().bins.raise_zero_threshold(threshold);
+            }
+        }
+
+        Ok((a, b))
+    }
+
+    /// Encode the sketch into a compact binary form, which `decode` turns back into an equal
+    /// sketch. This does not require the `use_serde` feature.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(ENCODING_MAGIC);
+        encoding::put_u8(&mut buf, ENCODING_VERSION);
 
-        let was_empty = self.store.count() == 0;
+        self.bins.config.encode(&mut buf);
+        encoding::put_f64(&mut buf, self.min);
+        encoding::put_f64(&mut buf, self.max);
+        encoding::put_f64(&mut buf, self.sum);
+        encoding::put_u64(&mut buf, self.bins.zero_count);
+        self.bins.store.encode(&mut buf);
+        self.bins.negative_store.encode(&mut buf);
+        self.moments.encode(&mut buf);
+        encoding::put_f64(&mut buf, self.sum_compensation);
 
-        // Merge the stores
-        self.store.merge(&o.store);
-        self.negative_store.merge(&o.negative_store);
-        self.zero_count += o.zero_count;
+        buf
+    }
+
+    /// Decode a sketch produced by `encode`. Result is an error, represented as
+    /// DDSketchError::Decode, if the input is not a valid encoded sketch.
+    pub fn decode(buf: &[u8]) -> Result<DDSketch> {
+        let mut r = Reader::new(buf);
+        if r.take(ENCODING_MAGIC.len())? != ENCODING_MAGIC || r.u8()? != ENCODING_VERSION {
+            return Err(DDSketchError::Decode);
+        }
+
+        let config = Config::decode(&mut r)?;
+        let (min, max, sum) = (r.f64()?, r.f64()?, r.f64()?);
+        let zero_count = r.u64()?;
+        let sketch = DDSketch {
+            bins: Bins {
+                config,
+                store: Store::decode(&mut r)?,
+                negative_store: Store::decode(&mut r)?,
+                zero_count,
+            },
+            min,
+            max,
+            sum,
+            moments: Moments::decode(&mut r)?,
+            sum_compensation: r.f64()?,
+        };
+        if r.remaining() > 0 {
+            return Err(DDSketchError::Decode);
+        }
+
+        // The total count must not overflow
+        let Bins {
+            store,
+            negative_store,
+            ..
+        } = &sketch.bins;
+        let count = [store.count(), negative_store.count()]
+            .iter()
+            .try_fold(zero_count, |sum, count| sum.checked_add(*count));
+        if count.and_then(|n| usize::try_from(n).ok()).is_none() {
+            return Err(DDSketchError::Decode);
+        }
+
+        // The stores must be those of the config, so they never grow past its bin limits
+        for (store, negative) in [(store, false), (negative_store, true)] {
+            let (direction, bin_limit) = config.store_limit(negative);
+            let bin_limit = if config.is_uniform() {
+                config.max_num_bins as usize
+            } else {
+                bin_limit
+            };
+            let limit = store.limit();
+            if limit.0 != direction || limit.1 > usize::max(bin_limit, 1) {
+                return Err(DDSketchError::Decode);
+            }
+        }
+
+        Ok(sketch)
+    }
+
+    pub(crate) fn bins(&self) -> &Bins {
+        &self.bins
+    }
+
+    fn empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    // The moments, if they cover every sample
+    fn moments(&self) -> Option<&Moments> {
+        if !self.empty() && self.moments.count() == self.count() as u64 {
+            Some(&self.moments)
+        } else {
+            None
+        }
+    }
+
+    // Add `count` samples of value `v` to min, max, sum and the moments, before adding them to
+    // the bins.
+    fn add_summary(&mut self, v: f64, count: u64) {
+        self.moments.add(v, count);
+        if v < self.min {
+            self.min = v;
+        }
+        if self.max < v {
+            self.max = v;
+        }
+        neumaier_add(&mut self.sum, &mut self.sum_compensation, v * count as f64);
+    }
+
+    // Merge min, max, sum and the moments of `o`, once its bins are merged
+    fn merge_summary(&mut self, o: &DDSketch, was_empty: bool) {
+        self.moments.merge(&o.moments);
 
         // Need to ensure we don't override min/max with initializers
         // if either store were empty
         if was_empty {
             self.min = o.min;
             self.max = o.max;
-        } else if o.store.count() > 0 {
+        } else if !o.empty() {
             if o.min < self.min {
                 self.min = o.min
             }
@@ -191,22 +809,82 @@ impl DDSketch {
                 self.max = o.max;
             }
         }
-        self.sum += o.sum;
 
-        Ok(())
+        neumaier_add(&mut self.sum, &mut self.sum_compensation, o.sum);
+        self.sum_compensation += o.sum_compensation;
     }
+}
 
-    fn empty(&self) -> bool {
-        self.count() == 0
+// Split the count of the bin for `key` of `from` between the bins of `to` it overlaps, in
+// proportion to the overlap. The counts passed to `f` always add up to `count`.
+fn split_bin(from: &Config, to: &Config, key: i32, count: u64, mut f: impl FnMut(i32, u64)) {
+    let lower = from.pow_gamma(key - 1);
+    let upper = from.pow_gamma(key);
+
+    let overlaps: Vec<(i32, f64)> = (to.key(lower)..=to.key(upper))
+        .map(|k| {
+            let overlap = f64::min(upper, to.pow_gamma(k)) - f64::max(lower, to.pow_gamma(k - 1));
+            (k, overlap / (upper - lower))
+        })
+        .filter(|(_, fraction)| *fraction > 0.0)
+        .collect();
+    let last = match overlaps.last() {
+        Some((k, _)) => *k,
+        None => return f(to.key(upper), count),
+    };
+
+    // Round the running total rather than each share, so no count is lost
+    let mut assigned = 0;
+    let mut total = 0.0;
+    for (k, fraction) in overlaps {
+        total += fraction * count as f64;
+        let share = if k == last {
+            count - assigned
+        } else {
+            u64::min((total + 0.5) as u64, count) - assigned
+        };
+        if share > 0 {
+            f(k, share);
+            assigned += share;
+        }
     }
 }
 
+// Add `v` to `sum` with Neumaier's variant of Kahan summation, accumulating the rounding error
+// in `compensation`. The accurate total is `sum + compensation`.
+fn neumaier_add(sum: &mut f64, compensation: &mut f64, v: f64) {
+    let t = *sum + v;
+    if sum.abs() >= v.abs() {
+        *compensation += (*sum - t) + v;
+    } else {
+        *compensation += (v - t) + *sum;
+    }
+    *sum = t;
+}
+
+// Values past the largest `Duration` saturate to it, as the upper bound of its bin does.
+// Negative values and NaN, which no duration maps to, are zero.
+fn to_duration(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        return Duration::ZERO;
+    }
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
 #[cfg(test)]
 mod tests {
     use approx::assert_relative_eq;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use std::time::Duration;
 
+    use crate::ddsketch::{to_duration, Accuracy, QuantileMethod};
+    use crate::test_util::{assert_same_quantiles, configs, random_sketches, random_values};
+    use crate::CollapseDirection;
     use crate::Config;
     use crate::DDSketch;
+    use crate::KeyMapping;
 
     #[test]
     fn test_add_zero() {
@@ -346,7 +1024,7 @@ mod tests {
         assert_eq!(dd.max(), Some(0.755188464));
         assert_eq!(dd.min(), Some(0.750064549));
         assert_eq!(dd.count(), 31);
-        assert_eq!(dd.sum(), Some(23.343630625000003));
+        assert_eq!(dd.sum(), Some(23.343630625));
 
         assert!(dd.quantile(0.25).unwrap().is_some());
         assert!(dd.quantile(0.5).unwrap().is_some());
@@ -370,4 +1048,919 @@ mod tests {
         dd.add(-3.0);
         assert_eq!(dd.length(), 256);
     }
+
+    #[test]
+    fn test_max_bytes() {
+        let max_bytes = 1024;
+        let c = Config::with_max_bytes(0.01, max_bytes, 1e-9);
+        let mut dd = DDSketch::new(c);
+
+        let values: Vec<f64> = (-600..600).map(|i| 1.02f64.powi(i)).collect();
+        for v in &values {
+            dd.add(*v);
+            dd.add(-*v);
+            assert!(dd.length() * 8 <= max_bytes);
+        }
+        assert_eq!(dd.count(), 2 * values.len());
+
+        // The error is uniform across quantiles, but coarser than requested.
+        let alpha = dd.alpha();
+        assert!(alpha > 0.01);
+        for (q, val) in [(0.1, -values[960]), (0.5, -values[0]), (0.9, values[959])] {
+            assert_relative_eq!(dd.quantile(q).unwrap().unwrap(), val, max_relative = alpha);
+        }
+    }
+
+    #[test]
+    fn test_max_bytes_merge() {
+        let max_bytes = 1024;
+        let c = Config::with_max_bytes(0.01, max_bytes, 1e-9);
+        let mut dd1 = DDSketch::new(c);
+        let mut dd2 = DDSketch::new(c);
+
+        for i in 1..11 {
+            dd1.add(i as f64);
+            dd2.add(i as f64 * 1e6);
+        }
+        assert_eq!(dd1.bins.config, c);
+        assert_eq!(dd2.bins.config, c);
+
+        // Each fits on its own, but not once merged.
+        dd1.merge(&dd2).unwrap();
+        assert_ne!(dd1.bins.config, c);
+        assert_eq!(dd1.count(), 20);
+        assert!(dd1.length() * 8 <= max_bytes);
+
+        let alpha = dd1.alpha();
+        assert_relative_eq!(
+            dd1.quantile(0.25).unwrap().unwrap(),
+            5.0,
+            max_relative = alpha
+        );
+        assert_relative_eq!(
+            dd1.quantile(0.75).unwrap().unwrap(),
+            5e6,
+            max_relative = alpha
+        );
+
+        // Merging back in the other direction collapses the uncollapsed sketch first.
+        dd2.merge(&dd1).unwrap();
+        assert_eq!(dd2.bins.config, dd1.bins.config);
+        assert_eq!(dd2.count(), 30);
+
+        let other = DDSketch::new(Config::with_max_bytes(0.02, max_bytes, 1e-9));
+        assert!(dd1.merge(&other).is_err());
+    }
+
+    #[test]
+    fn test_uniform_collapsing() {
+        let c = Config::uniform_collapsing(0.01, 256, 1e-9);
+        let mut dd = DDSketch::new(c);
+        assert_relative_eq!(dd.alpha(), 0.01);
+
+        for i in 100..=1000 {
+            dd.add(i as f64 / 1000.0);
+        }
+        assert_relative_eq!(dd.alpha(), 0.01);
+
+        for i in 100..=1000 {
+            dd.add(i as f64 * 10.0);
+        }
+        assert!(dd.length() <= 256);
+
+        // Each collapse squares gamma
+        let alpha = dd.alpha();
+        let gamma = (1.0 + alpha) / (1.0 - alpha);
+        assert_relative_eq!(gamma, c.gamma.powi(4), max_relative = 1e-9);
+
+        // The lowest quantiles keep the same guarantee as the highest.
+        for (q, val) in [
+            (0.001, 0.101),
+            (0.25, 0.55),
+            (0.75, 5490.0),
+            (0.999, 9990.0),
+        ] {
+            assert_relative_eq!(dd.quantile(q).unwrap().unwrap(), val, max_relative = alpha);
+        }
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let mut dd = DDSketch::default();
+        dd.add_f32(1.5);
+        dd.add_u64(2);
+        dd.add_i64(-3);
+        assert_eq!(dd.count(), 3);
+        assert_eq!(dd.min(), Some(-3.0));
+        assert_eq!(dd.max(), Some(2.0));
+
+        let mut dd = DDSketch::default();
+        assert_eq!(dd.quantile_duration(0.5).unwrap(), None);
+        for ms in 1..=100 {
+            dd.add_duration(Duration::from_millis(ms));
+        }
+
+        let p50 = dd.quantile_duration(0.5).unwrap().unwrap().as_secs_f64();
+        assert_relative_eq!(p50, 0.050, max_relative = 0.01);
+        assert_eq!(dd.min_duration(), Some(Duration::from_millis(1)));
+        assert_eq!(dd.max_duration(), Some(Duration::from_millis(100)));
+        assert!(dd.quantile_duration(1.5).is_err());
+
+        let mut dd = DDSketch::default();
+        dd.add_duration(Duration::MAX);
+        assert_eq!(dd.max_duration(), Some(Duration::MAX));
+        assert_eq!(dd.min_duration(), Some(Duration::MAX));
+        let p50 = dd.quantile_duration(0.5).unwrap().unwrap().as_secs_f64();
+        assert_relative_eq!(p50, Duration::MAX.as_secs_f64(), max_relative = 0.01);
+        assert_eq!(dd.quantile_duration(1.0).unwrap(), Some(Duration::MAX));
+        assert_eq!(to_duration(f64::NAN), Duration::ZERO);
+        assert_eq!(to_duration(-1.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut dd = DDSketch::new(Config::uniform_collapsing(0.01, 64, 1e-9));
+        for i in -100..100 {
+            dd.add(i as f64 * 1.5);
+        }
+
+        let buf = dd.encode();
+        let decoded = DDSketch::decode(&buf).unwrap();
+        assert_eq!(decoded.config(), dd.config());
+        assert_eq!(decoded.count(), dd.count());
+        assert_eq!(decoded.zero_count(), 1);
+        assert_eq!(decoded.sum(), dd.sum());
+        assert!(decoded.positive_bins().eq(dd.positive_bins()));
+        assert!(decoded.negative_bins().eq(dd.negative_bins()));
+        for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_eq!(decoded.quantile(q).unwrap(), dd.quantile(q).unwrap());
+        }
+        assert_eq!(decoded.encode(), buf);
+
+        // Decoded sketches keep working
+        let mut decoded = decoded;
+        decoded.merge(&dd).unwrap();
+        decoded.add(1e6);
+        assert_eq!(decoded.count(), 2 * dd.count() + 1);
+
+        let empty = DDSketch::default();
+        assert_eq!(DDSketch::decode(&empty.encode()).unwrap().count(), 0);
+        let mut positive = DDSketch::new(Config::uniform_collapsing(0.01, 64, 1e-9));
+        positive.add(1.0);
+        assert_eq!(DDSketch::decode(&positive.encode()).unwrap().count(), 1);
+
+        assert!(DDSketch::decode(&buf[..buf.len() - 1]).is_err());
+        assert!(DDSketch::decode(&[buf.as_slice(), &[0]].concat()).is_err());
+        assert!(DDSketch::decode(b"DDSK").is_err());
+        assert!(DDSketch::decode(&[]).is_err());
+    }
+
+    // Bytes encoded by the current version, with the sketch of `test_golden_bytes`. Any change to
+    // the encoding must bump `ENCODING_VERSION` and these bytes along with it.
+    const GOLDEN: &[u8] = &[
+        0x44, 0x44, 0x53, 0x4b, // magic
+        0x01, // version
+        0x04, 0x00, 0x00, 0x00, // max_num_bins
+        0x2a, 0x78, 0x39, 0x05, 0x2f, 0xa7, 0xf0, 0x3f, // gamma
+        0xf2, 0xa9, 0xa9, 0x47, 0x94, 0x7b, 0xa4, 0x3f, // gamma_ln
+        0x95, 0xd6, 0x26, 0xe8, 0x0b, 0x2e, 0x11, 0x3e, // min_value
+        0x07, 0x02, 0x00, 0x00, // offset
+        0x00, // uniform
+        0x00, 0x00, 0x00, 0x00, // collapses
+        0x00, // mapping
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // zero_threshold
+        0x00, // positive_collapse
+        0x00, 0x00, 0x00, 0x00, // positive_max_num_bins
+        0x00, // negative_collapse
+        0x00, 0x00, 0x00, 0x00, // negative_max_num_bins
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, // min
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x40, // max
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x14, 0x40, // sum
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // zero_count
+        0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // store: bin_limit
+        0x01, // is_collapsed
+        0x00, // direction
+        0x19, 0x00, 0x00, 0x00, // min_key
+        0x04, 0x00, 0x00, 0x00, // len
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // count
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, // negative_store: bin_limit
+        0x00, // is_collapsed
+        0x00, // direction
+        0x12, 0x00, 0x00, 0x00, // min_key
+        0x01, 0x00, 0x00, 0x00, // len
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // count
+        0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xef, 0x3f, // moments: mean
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x32, 0x40, // m2
+        0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0x27, 0xc0, // m3
+        0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // count
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // sum_compensation
+    ];
+
+    #[test]
+    fn test_golden_bytes() {
+        let dd = DDSketch::decode(GOLDEN).unwrap();
+        assert_eq!(dd.config().max_num_bins, 4);
+        assert_eq!(dd.count(), 5);
+        assert_eq!(dd.zero_count(), 1);
+        assert_eq!(dd.min(), Some(-2.0));
+        assert_eq!(dd.max(), Some(3.0));
+        assert_eq!(dd.sum(), Some(5.0));
+        assert_eq!(dd.moments.count(), 5);
+        assert!(dd.is_collapsed());
+        assert!(dd.positive_bins().eq([(25, 1), (28, 2)]));
+        assert!(dd.negative_bins().eq([(18, 1)]));
+        assert_eq!(dd.encode(), GOLDEN);
+
+        // The same samples land in the same bins
+        let mut expected = DDSketch::new(Config::new(0.02, 4, 1e-9));
+        expected.add(1.0);
+        expected.add_with_count(3.0, 2);
+        expected.add(-2.0);
+        expected.add(0.0);
+        assert_eq!(expected.config(), dd.config());
+        assert!(expected.positive_bins().eq(dd.positive_bins()));
+        assert!(expected.negative_bins().eq(dd.negative_bins()));
+
+        // Sketches encoded by other versions are rejected
+        let mut other = GOLDEN.to_vec();
+        other[4] = 2;
+        assert!(DDSketch::decode(&other).is_err());
+    }
+
+    #[test]
+    fn test_decode_corrupted() {
+        let mut dd = DDSketch::new(Config::uniform_collapsing(0.01, 64, 1e-9));
+        for i in -100..100 {
+            dd.add(i as f64 * 1.5);
+        }
+        let buf = dd.encode();
+
+        // Corrupted input, as may come through the FFI, is rejected or decodes to a sketch that
+        // can still be queried, but never panics
+        let mut rng = StdRng::seed_from_u64(42);
+        for i in 0..buf.len() * 8 {
+            let mut corrupted = buf.clone();
+            corrupted[i / 8] ^= 1 << (i % 8);
+            if rng.gen_bool(0.5) {
+                let j = rng.gen_range(0..buf.len());
+                corrupted[j] = rng.gen();
+            }
+            if let Ok(decoded) = DDSketch::decode(&corrupted) {
+                decoded.quantile(0.5).unwrap();
+                assert_eq!(decoded.encode(), corrupted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_min_max_without_positive_samples() {
+        // Sketches holding only negative samples or zeros still have a min and max to merge
+        let mut negative = DDSketch::default();
+        negative.add(-5.0);
+        let mut zero = DDSketch::default();
+        zero.add(0.0);
+        let mut positive = DDSketch::default();
+        positive.add(3.0);
+
+        let mut dd = positive.clone();
+        dd.merge(&negative).unwrap();
+        assert_eq!(dd.min(), Some(-5.0));
+        assert_eq!(dd.max(), Some(3.0));
+
+        let mut dd = negative.clone();
+        dd.merge(&positive).unwrap();
+        assert_eq!(dd.min(), Some(-5.0));
+        assert_eq!(dd.max(), Some(3.0));
+
+        let mut dd = zero.clone();
+        dd.merge(&negative).unwrap();
+        assert_eq!(dd.min(), Some(-5.0));
+        assert_eq!(dd.max(), Some(0.0));
+
+        let mut dd = DDSketch::default();
+        dd.merge(&zero).unwrap();
+        assert_eq!(dd.min(), Some(0.0));
+        assert_eq!(dd.max(), Some(0.0));
+    }
+
+    #[test]
+    fn test_add_with_count() {
+        let mut dd1 = DDSketch::default();
+        let mut dd2 = DDSketch::default();
+        for v in [-2.0, 0.0, 3.0] {
+            dd1.add_with_count(v, 3);
+            for _ in 0..3 {
+                dd2.add(v);
+            }
+        }
+        dd1.add_with_count(100.0, 0);
+
+        assert_eq!(dd1.count(), 9);
+        assert_eq!(dd1.sum(), dd2.sum());
+        assert_eq!(dd1.max(), Some(3.0));
+        assert!(dd1.positive_bins().eq(dd2.positive_bins()));
+        assert!(dd1.negative_bins().eq(dd2.negative_bins()));
+        assert_eq!(dd1.zero_count(), dd2.zero_count());
+        assert_relative_eq!(dd1.variance().unwrap(), dd2.variance().unwrap());
+    }
+
+    #[test]
+    fn test_from_bins() {
+        for dd in random_sketches(42) {
+            let rebuilt = DDSketch::from_bins(
+                *dd.config(),
+                dd.positive_bins(),
+                dd.negative_bins(),
+                dd.zero_count(),
+            );
+            assert_eq!(rebuilt.count(), dd.count());
+            assert!(rebuilt.positive_bins().eq(dd.positive_bins()));
+            assert!(rebuilt.negative_bins().eq(dd.negative_bins()));
+            assert_same_quantiles(
+                |q, method| rebuilt.quantile_with_method(q, method),
+                |q, method| dd.quantile_with_method(q, method),
+            );
+            let alpha = dd.alpha();
+            assert_relative_eq!(
+                rebuilt.min().unwrap(),
+                dd.min().unwrap(),
+                max_relative = alpha
+            );
+            assert_relative_eq!(
+                rebuilt.max().unwrap(),
+                dd.max().unwrap(),
+                max_relative = alpha
+            );
+            assert_relative_eq!(
+                rebuilt.mean().unwrap(),
+                dd.mean().unwrap(),
+                max_relative = 2.0 * alpha
+            );
+
+            let rebuilt = DDSketch::from_bins_with_summary(
+                *dd.config(),
+                dd.positive_bins(),
+                dd.negative_bins(),
+                dd.zero_count(),
+                dd.min().unwrap(),
+                dd.max().unwrap(),
+                dd.sum().unwrap(),
+            );
+            assert_eq!(rebuilt.min(), dd.min());
+            assert_eq!(rebuilt.max(), dd.max());
+            assert_eq!(rebuilt.sum(), dd.sum());
+            assert_eq!(rebuilt.quantile(0.0).unwrap(), dd.quantile(0.0).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_add_key() {
+        let mut dd = DDSketch::default();
+        let key = dd.config().key(5.0);
+        dd.add_positive_key(key, 3);
+        dd.add_negative_key(key, 1);
+        dd.add_zero_count(2);
+        dd.add_zero_count(0);
+
+        assert_eq!(dd.count(), 6);
+        assert_eq!(dd.zero_count(), 2);
+        assert!(dd.positive_bins().eq([(key, 3)]));
+        assert!(dd.negative_bins().eq([(key, 1)]));
+        assert_relative_eq!(dd.max().unwrap(), 5.0, max_relative = 0.01);
+        assert_relative_eq!(dd.min().unwrap(), -5.0, max_relative = 0.01);
+        assert_relative_eq!(dd.sum().unwrap(), 10.0, max_relative = 0.01);
+
+        // Keys past the range of the stores land in their edge bins
+        let extreme = [i32::MIN, -1_500_000_000, 0, 1_500_000_000, i32::MAX];
+        for config in [
+            Config::defaults(),
+            Config::uniform_collapsing(0.01, 128, 1e-9),
+            Config::defaults().with_positive_collapse(CollapseDirection::Highest, 128),
+        ] {
+            for keys in [&extreme[..], &[i32::MAX, i32::MIN], &[i32::MIN, i32::MAX]] {
+                let bins = keys.iter().map(|key| (*key, 1));
+                let dd = DDSketch::from_bins(config, bins.clone(), bins, 0);
+                assert_eq!(dd.count(), 2 * keys.len());
+                assert!(dd.quantile(0.5).unwrap().is_some());
+
+                let mut dd = DDSketch::new(config);
+                for key in keys {
+                    dd.add_positive_key(*key, 1);
+                    dd.add_negative_key(*key, 1);
+                }
+                assert_eq!(dd.count(), 2 * keys.len());
+                assert!(dd.quantile(0.5).unwrap().is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_threshold() {
+        let config = Config::defaults().with_zero_threshold(1e-3);
+        assert_eq!(config.zero_threshold(), 1e-3);
+        assert_eq!(config.offset, Config::defaults().offset);
+        assert_eq!(
+            Config::defaults().with_zero_threshold(0.0),
+            Config::defaults()
+        );
+
+        let mut dd1 = DDSketch::new(config);
+        for v in [-1e-4, 5e-4, 1e-3, 2e-3, -1.0] {
+            dd1.add(v);
+        }
+        assert_eq!(dd1.zero_count(), 3);
+        assert_eq!(dd1.min(), Some(-1.0));
+        assert_eq!(DDSketch::decode(&dd1.encode()).unwrap().config(), &config);
+
+        // Merging raises the threshold to the larger one, whichever side it is on
+        let mut dd2 = DDSketch::default();
+        for v in [-2e-4, 1e-6, 1e-2, 3.0] {
+            dd2.add(v);
+        }
+        let mut merged = dd2.clone();
+        merged.merge(&dd1).unwrap();
+        assert_eq!(merged.config(), &config);
+        assert_eq!(merged.count(), 9);
+        assert_eq!(merged.zero_count(), 5);
+        assert_eq!(merged.quantile(1.0).unwrap(), Some(3.0));
+        assert!(merged.rank(1e-3) >= 6);
+
+        let mut merged_all = DDSketch::new(config);
+        merged_all.merge_all([&dd1, &dd2]).unwrap();
+        assert!(merged_all.positive_bins().eq(merged.positive_bins()));
+        assert!(merged_all.negative_bins().eq(merged.negative_bins()));
+        assert_eq!(merged_all.zero_count(), merged.zero_count());
+
+        let mut light = crate::LightDDSketch::from(&dd2);
+        light.merge(&crate::LightDDSketch::from(&dd1)).unwrap();
+        assert_eq!(light.zero_count(), 5);
+
+        assert!(merged
+            .merge(&DDSketch::new(Config::new(0.02, 2048, 1e-9)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_collapse_direction() {
+        let config =
+            Config::new(0.01, 2048, 1e-9).with_negative_collapse(CollapseDirection::Highest, 128);
+        let mut dd = DDSketch::new(config);
+        let mut dd2 = DDSketch::new(config);
+        for i in 1..=1000 {
+            dd.add(-1.02f64.powi(i));
+            dd2.add(1.02f64.powi(i));
+            dd2.add(-1.02f64.powi(i));
+        }
+        assert!(dd.is_collapsed());
+        assert_eq!(dd.negative_bins().count(), 128);
+        assert_eq!(
+            DDSketch::decode(&dd.encode()).unwrap().encode(),
+            dd.encode()
+        );
+
+        // The negative samples closest to zero keep their accuracy, the most negative lose it
+        let (q, accuracy) = dd.quantile_with_guarantee(0.99).unwrap().unwrap();
+        assert_relative_eq!(q, -1.02f64.powi(11), max_relative = 0.01);
+        assert_eq!(accuracy, Accuracy::Guaranteed);
+        let (_, accuracy) = dd.quantile_with_guarantee(0.5).unwrap().unwrap();
+        assert_eq!(accuracy, Accuracy::Collapsed);
+        assert!(dd.negative_collapsed_count() > 800);
+
+        dd2.merge(&dd).unwrap();
+        assert_eq!(
+            dd2.negative_collapsed_count(),
+            2 * dd.negative_collapsed_count()
+        );
+        assert!(!dd2.positive_bins().any(|(_, count)| count > 2));
+        assert!(dd
+            .merge(&DDSketch::new(Config::new(0.01, 2048, 1e-9)))
+            .is_err());
+
+        let downsampled = dd2.downsample(0.03);
+        assert_eq!(downsampled.count(), dd2.count());
+        assert!(downsampled.negative_bins().count() < 128);
+    }
+
+    #[test]
+    fn test_merge_all() {
+        for config in configs() {
+            // Sketches of different sizes and ranges, so they collapse differently
+            let sketches: Vec<DDSketch> = (0..20)
+                .map(|i| {
+                    let mut dd = DDSketch::new(config);
+                    for v in &random_values(i)[..i as usize * 10] {
+                        dd.add(if i % 3 == 0 { v / 1e6 } else { *v });
+                    }
+                    dd
+                })
+                .collect();
+
+            let mut sequential = DDSketch::new(config);
+            for dd in &sketches {
+                sequential.merge(dd).unwrap();
+            }
+            let mut all = DDSketch::new(config);
+            all.merge_all(&sketches).unwrap();
+            assert_eq!(all.encode(), sequential.encode());
+
+            // Merging into a non-empty sketch
+            let mut sequential = sketches[5].clone();
+            for dd in &sketches[10..] {
+                sequential.merge(dd).unwrap();
+            }
+            let mut all = sketches[5].clone();
+            all.merge_all(&sketches[10..]).unwrap();
+            assert_eq!(all.encode(), sequential.encode());
+        }
+
+        let mut dd = DDSketch::default();
+        dd.add(1.0);
+        let other = DDSketch::new(Config::new(0.02, 2048, 1e-9));
+        assert!(dd.merge_all([&DDSketch::default(), &other]).is_err());
+        assert_eq!(dd.count(), 1);
+
+        // Nor when a sketch with another zero threshold comes first
+        let mut raised = DDSketch::new(Config::defaults().with_zero_threshold(10.0));
+        raised.add(5.0);
+        assert!(dd.merge_all([&raised, &other]).is_err());
+        assert_eq!(dd.count(), 1);
+        assert_eq!(dd.config(), &Config::defaults());
+    }
+
+    #[test]
+    fn test_convert_to() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut values: Vec<f64> = (0..10_000).map(|_| rng.gen_range(-10.0..1000.0)).collect();
+        values.push(0.0);
+        let mut dd = DDSketch::default();
+        for v in &values {
+            dd.add(*v);
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for config in [
+            Config::new(0.02, 2048, 1e-9),
+            Config::new(0.005, 4096, 1e-3),
+            Config::uniform_collapsing(0.01, 128, 1e-9),
+        ] {
+            let converted = dd.convert_to(&config);
+            assert!(converted.alpha() >= config.alpha() - 1e-12);
+            assert_eq!(converted.count(), dd.count());
+            assert_eq!(converted.sum(), dd.sum());
+            assert_eq!(converted.min(), dd.min());
+
+            // Within the documented bound, allowing for the rank of the quantile moving by one
+            let alpha = dd.config().gamma * (1.0 + converted.alpha()) - 1.0;
+            for q in [0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+                let rank = (q * (values.len() - 1) as f64) as usize;
+                let actual = converted.quantile(q).unwrap().unwrap();
+                let lower = values[rank - 1] - alpha * values[rank - 1].abs();
+                let upper = values[rank + 1] + alpha * values[rank + 1].abs();
+                assert!(lower <= actual && actual <= upper, "q={} {}", q, actual);
+            }
+        }
+
+        assert_eq!(dd.convert_to(dd.config()).encode(), dd.encode());
+
+        // Bins are compared to the zero threshold once remapped by the collapses while adding
+        let config = Config::uniform_collapsing(0.01, 128, 1e-9).with_zero_threshold(20.0);
+        let converted = dd.convert_to(&config);
+        assert!(converted.config().collapses() > 0);
+        let threshold = converted.config().zero_threshold();
+        assert!(converted
+            .positive_bins()
+            .all(|(key, _)| converted.config().pow_gamma(key) > threshold));
+        assert_eq!(converted.negative_bins().count(), 0);
+    }
+
+    #[test]
+    fn test_merge_lossy() {
+        let mut dd = DDSketch::default();
+        let mut other = DDSketch::new(Config::new(0.005, 2048, 1e-9));
+        for i in 1..=100 {
+            dd.add(i as f64);
+            other.add(i as f64 * 2.0);
+        }
+
+        assert!(dd.merge(&other).is_err());
+        dd.merge_lossy(&other).unwrap();
+        assert_eq!(dd.count(), 200);
+        assert_eq!(dd.max(), Some(200.0));
+        assert_relative_eq!(
+            dd.quantile(0.5).unwrap().unwrap(),
+            67.0,
+            max_relative = 0.03
+        );
+    }
+
+    #[cfg(feature = "use_serde")]
+    #[test]
+    fn test_deserialize_without_moments() {
+        let mut dd = DDSketch::default();
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+
+        // Keep only the fields serialized before the moments were tracked
+        let mut json = serde_json::to_value(&dd).unwrap();
+        let retain = |value: &mut serde_json::Value, fields: &[&str]| {
+            value
+                .as_object_mut()
+                .unwrap()
+                .retain(|k, _| fields.contains(&k.as_str()))
+        };
+        let store = [
+            "bins",
+            "count",
+            "min_key",
+            "max_key",
+            "offset",
+            "bin_limit",
+            "is_collapsed",
+        ];
+        retain(&mut json["store"], &store);
+        retain(&mut json["negative_store"], &store);
+        retain(
+            &mut json["config"],
+            &["max_num_bins", "gamma", "gamma_ln", "min_value", "offset"],
+        );
+        retain(
+            &mut json,
+            &[
+                "config",
+                "store",
+                "negative_store",
+                "min",
+                "max",
+                "sum",
+                "zero_count",
+            ],
+        );
+
+        let old: DDSketch = serde_json::from_value(json).unwrap();
+        assert_eq!(old.count(), 100);
+        assert_eq!(old.quantile(0.5).unwrap(), dd.quantile(0.5).unwrap());
+        assert_eq!(old.mean(), Some(50.5));
+        assert_eq!(old.variance(), None);
+        assert_eq!(old.stddev(), None);
+        assert_eq!(old.skewness(), None);
+
+        // Nor are they known once merged with other samples
+        let mut merged = dd.clone();
+        merged.merge(&old).unwrap();
+        assert_eq!(merged.mean(), Some(50.5));
+        assert_eq!(merged.variance(), None);
+        let mut merged = DDSketch::default();
+        merged.merge_all(vec![&old, &dd]).unwrap();
+        assert_eq!(merged.skewness(), None);
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        let mut dd = DDSketch::default();
+        dd.add(1e16);
+        for _ in 0..1000 {
+            dd.add(1.0);
+        }
+        dd.add(-1e16);
+        assert_eq!(dd.sum(), Some(1000.0));
+        assert_eq!(dd.mean(), Some(1000.0 / 1002.0));
+
+        let mut other = DDSketch::default();
+        for _ in 0..10 {
+            other.add(0.1);
+        }
+        let mut merged = DDSketch::default();
+        for _ in 0..100 {
+            merged.merge(&other).unwrap();
+        }
+        assert_eq!(merged.sum(), Some(100.0));
+
+        let mut all = DDSketch::default();
+        all.merge_all(vec![&other; 100]).unwrap();
+        assert_eq!(all.sum(), Some(100.0));
+        assert_eq!(DDSketch::decode(&dd.encode()).unwrap().sum(), Some(1000.0));
+    }
+
+    #[test]
+    fn test_quantile_with_guarantee() {
+        let mut dd = DDSketch::new(Config::new(0.01, 128, 1e-9));
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            dd.add(-(i as f64));
+        }
+        assert!(dd.is_collapsed());
+
+        // Keys 0 to 346 do not fit in 128 bins, so values up to 79 collapse into key 219
+        assert_eq!(dd.positive_collapsed_count(), 79);
+        assert_eq!(dd.negative_collapsed_count(), 79);
+
+        let (v, accuracy) = dd.quantile_with_guarantee(0.5).unwrap().unwrap();
+        assert_eq!(accuracy, Accuracy::Collapsed);
+        assert!(v < 0.0);
+        let (v, accuracy) = dd.quantile_with_guarantee(0.51).unwrap().unwrap();
+        assert_eq!(accuracy, Accuracy::Collapsed);
+        assert_relative_eq!(v, 79.0, max_relative = 0.01);
+        let (v, accuracy) = dd.quantile_with_guarantee(0.9).unwrap().unwrap();
+        assert_eq!(accuracy, Accuracy::Guaranteed);
+        assert_relative_eq!(v, 800.0, max_relative = 0.01);
+        assert_eq!(
+            dd.quantile_with_guarantee(0.0).unwrap(),
+            Some((-1000.0, Accuracy::Guaranteed))
+        );
+
+        let mut dd = DDSketch::default();
+        assert_eq!(dd.quantile_with_guarantee(0.5).unwrap(), None);
+        dd.add(1.0);
+        assert_eq!(dd.positive_collapsed_count(), 0);
+        assert_eq!(
+            dd.quantile_with_guarantee(0.5).unwrap().unwrap().1,
+            Accuracy::Guaranteed
+        );
+    }
+
+    #[test]
+    fn test_quantile_with_method() {
+        let mut dd = DDSketch::default();
+        assert_eq!(
+            dd.quantile_with_method(0.5, QuantileMethod::Linear)
+                .unwrap(),
+            None
+        );
+        assert!(dd.quantile_with_method(1.5, QuantileMethod::Upper).is_err());
+
+        for i in 1..=4 {
+            dd.add(i as f64);
+        }
+
+        // The rank of the median is 1.5, between samples 2.0 and 3.0
+        let cases = [
+            (QuantileMethod::Lower, 2.0),
+            (QuantileMethod::Upper, 3.0),
+            (QuantileMethod::Nearest, 3.0),
+            (QuantileMethod::Linear, 2.5),
+        ];
+        for (method, expected) in cases {
+            let actual = dd.quantile_with_method(0.5, method).unwrap().unwrap();
+            assert_relative_eq!(actual, expected, max_relative = 0.01);
+        }
+
+        // Rank 1.25 is nearest to sample 2.0, and rank 2.5 rounds to the even rank 2
+        let nearest = |q| dd.quantile_with_method(q, QuantileMethod::Nearest).unwrap();
+        assert_relative_eq!(nearest(1.25 / 3.0).unwrap(), 2.0, max_relative = 0.01);
+        assert_relative_eq!(nearest(2.5 / 3.0).unwrap(), 3.0, max_relative = 0.01);
+
+        for q in [0.0, 0.1, 0.5, 0.99, 1.0] {
+            assert_eq!(
+                dd.quantile_with_method(q, QuantileMethod::default())
+                    .unwrap(),
+                dd.quantile(q).unwrap()
+            );
+        }
+        assert_eq!(
+            dd.quantile_with_method(1.0, QuantileMethod::Linear)
+                .unwrap(),
+            Some(4.0)
+        );
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut dd = DDSketch::default();
+        for _ in 0..10_000 {
+            dd.add(rng.gen_range(-100.0..1e4));
+        }
+
+        // Folding three bins into one, exactly as if added with the coarser accuracy
+        let gamma = dd.config().gamma;
+        let alpha = (gamma.powi(3) - 1.0) / (gamma.powi(3) + 1.0);
+        let folded = dd.downsample(alpha);
+        assert_relative_eq!(folded.alpha(), alpha, max_relative = 1e-9);
+        assert!(folded.length() < dd.length());
+        assert_eq!(folded.count(), dd.count());
+        assert_eq!(folded.sum(), dd.sum());
+        assert_eq!(folded.min(), dd.min());
+
+        let config = Config::new(alpha, 2048, 1e-9);
+        let mut direct = DDSketch::new(config);
+        let mut reference = DDSketch::default();
+        for _ in 0..10_000 {
+            let v = rng.gen_range(-100.0..1e4);
+            direct.add(v);
+            reference.add(v);
+        }
+        let mut folded = reference.downsample(alpha);
+        assert_eq!(folded.config(), direct.config());
+        assert!(folded.positive_bins().eq(direct.positive_bins()));
+        assert!(folded.negative_bins().eq(direct.negative_bins()));
+
+        // Both ways round, with a sketch freshly built with that accuracy
+        let mut fresh = DDSketch::new(config);
+        fresh.add(1.0);
+        fresh.merge(&folded).unwrap();
+        folded.merge(&DDSketch::new(config)).unwrap();
+        assert_eq!(fresh.count(), folded.count() + 1);
+
+        // Other accuracies are re-bucketed
+        let converted = dd.downsample(0.05);
+        assert_relative_eq!(converted.alpha(), 0.05, max_relative = 1e-9);
+        assert_eq!(converted.count(), dd.count());
+        assert_relative_eq!(
+            converted.quantile(0.5).unwrap().unwrap(),
+            dd.quantile(0.5).unwrap().unwrap(),
+            max_relative = 0.07
+        );
+
+        assert_eq!(dd.downsample(0.005).encode(), dd.encode());
+
+        // The key mapping is kept, so the result merges with sketches of the coarser config
+        let cubic = Config::defaults().with_mapping(KeyMapping::CubicInterpolated);
+        let mut dd = DDSketch::new(cubic);
+        let mut other = DDSketch::new(
+            Config::new(0.05, 2048, 1e-9).with_mapping(KeyMapping::CubicInterpolated),
+        );
+        for i in 1..=1000 {
+            dd.add(i as f64);
+            other.add(-(i as f64));
+        }
+        let mut converted = dd.downsample(0.05);
+        assert_eq!(converted.config(), other.config());
+        converted.merge(&other).unwrap();
+        assert_eq!(converted.count(), 2000);
+        assert_relative_eq!(
+            converted.quantile(0.75).unwrap().unwrap(),
+            500.0,
+            max_relative = 0.1
+        );
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut dd = DDSketch::default();
+        assert_eq!(dd.rank(1.0), 0);
+
+        for v in [-2.0, -1.0, 0.0, 1.0, 2.0, 3.0] {
+            dd.add(v);
+        }
+        assert_eq!(dd.rank(-3.0), 0);
+        assert_eq!(dd.rank(-2.0), 1);
+        assert_eq!(dd.rank(-1.5), 1);
+        assert_eq!(dd.rank(0.0), 3);
+        assert_eq!(dd.rank(2.5), 5);
+        assert_eq!(dd.rank(100.0), 6);
+    }
+
+    #[test]
+    fn test_add_slice() {
+        for config in configs() {
+            let mut one_by_one = DDSketch::new(config);
+            let mut sliced = DDSketch::new(config);
+            // Batches of growing values, so the stores grow and collapse between them
+            for batch in 0..5 {
+                let values: Vec<f64> = random_values(batch as u64)[..200]
+                    .iter()
+                    .map(|v| v * 10f64.powi(batch))
+                    .collect();
+                for v in &values {
+                    one_by_one.add(*v);
+                }
+                sliced.add_slice(&values);
+                assert_eq!(sliced.encode(), one_by_one.encode());
+            }
+        }
+
+        let mut dd = DDSketch::default();
+        dd.add_slice(&[]);
+        assert_eq!(dd.count(), 0);
+    }
+
+    #[test]
+    fn test_moments() {
+        let mut dd = DDSketch::default();
+        assert_eq!(dd.mean(), None);
+        assert_eq!(dd.variance(), None);
+        assert_eq!(dd.stddev(), None);
+        assert_eq!(dd.skewness(), None);
+
+        let mut other = DDSketch::default();
+        dd.add(2.0);
+        dd.add_with_count(4.0, 3);
+        other.add(5.0);
+        other.add(5.0);
+        other.add(7.0);
+        other.add(9.0);
+        dd.merge(&other).unwrap();
+
+        // {2, 4, 4, 4, 5, 5, 7, 9}
+        assert_eq!(dd.mean(), Some(5.0));
+        assert_eq!(dd.variance(), Some(4.0));
+        assert_eq!(dd.stddev(), Some(2.0));
+        assert_relative_eq!(dd.skewness().unwrap(), 0.65625);
+
+        let decoded = DDSketch::decode(&dd.encode()).unwrap();
+        assert_eq!(decoded.variance(), dd.variance());
+    }
 }
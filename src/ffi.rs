@@ -0,0 +1,381 @@
+/*!
+C bindings for `DDSketch`, enabled by the `ffi` feature. The matching header is
+`include/ddsketch.h`, maintained by hand, so update it along with the functions here.
+tests/ffi/test_ffi.c calls every function through it, and CI builds and runs it against the
+library to catch a header out of sync. Build a shared or static library with:
+
+```sh
+cargo rustc --release --features ffi --crate-type cdylib
+cargo rustc --release --features ffi --crate-type staticlib
+```
+
+Sketches are handed out as opaque pointers, created by `ddsketch_new` or `ddsketch_decode`
+and released with `ddsketch_free`. Functions that can fail return a `DDSketchStatus`, with
+`DDSketchStatus::Ok` on success.
+
+Panics never unwind into the caller. They are caught and reported as `DDSketchStatus::Panic`,
+or as null or 0 by the functions returning a pointer or a count.
+ */
+
+use core::ptr;
+use core::slice;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError};
+
+/// Result of the fallible C functions
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DDSketchStatus {
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = 1,
+    /// The quantile was not between 0 and 1 (inclusive), see `DDSketchError::Quantile`
+    InvalidQuantile = 2,
+    /// The sketches have different configs, see `DDSketchError::Merge`
+    IncompatibleMerge = 3,
+    /// The buffer does not hold an encoded sketch, see `DDSketchError::Decode`
+    InvalidEncoding = 4,
+    /// The sketch holds no samples
+    Empty = 5,
+    /// The buffer is too small for the encoded sketch
+    BufferTooSmall = 6,
+    /// The function panicked. The sketches it was passed may be left inconsistent and should
+    /// only be freed.
+    Panic = 7,
+    /// A sketch was passed as both the sketch to change and another argument
+    SameSketch = 8,
+}
+
+impl From<DDSketchError> for DDSketchStatus {
+    fn from(e: DDSketchError) -> Self {
+        match e {
+            DDSketchError::Quantile => DDSketchStatus::InvalidQuantile,
+            DDSketchError::Merge => DDSketchStatus::IncompatibleMerge,
+            DDSketchError::Decode => DDSketchStatus::InvalidEncoding,
+        }
+    }
+}
+
+/// Create a sketch, see `Config::new`. Returns null if `alpha` is not between 0 and 1
+/// (exclusive), or `max_num_bins` or `min_value` is not positive.
+#[no_mangle]
+pub extern "C" fn ddsketch_new(alpha: f64, max_num_bins: u32, min_value: f64) -> *mut DDSketch {
+    if !(alpha > 0.0 && alpha < 1.0 && max_num_bins > 0 && min_value > 0.0) {
+        return ptr::null_mut();
+    }
+
+    catch(ptr::null_mut(), || {
+        into_handle(DDSketch::new(Config::new(alpha, max_num_bins, min_value)))
+    })
+}
+
+/// Create a sketch using the default config, see `Config::defaults`
+#[no_mangle]
+pub extern "C" fn ddsketch_new_default() -> *mut DDSketch {
+    catch(ptr::null_mut(), || into_handle(DDSketch::default()))
+}
+
+/// Free a sketch. Does nothing if `sketch` is null.
+///
+/// # Safety
+///
+/// `sketch` must be null or a sketch that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_free(sketch: *mut DDSketch) {
+    if !sketch.is_null() {
+        catch((), || drop(Box::from_raw(sketch)));
+    }
+}
+
+/// Add the sample to the sketch
+///
+/// # Safety
+///
+/// `sketch` must be null or a live sketch.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_add(sketch: *mut DDSketch, v: f64) -> DDSketchStatus {
+    ddsketch_add_with_count(sketch, v, 1)
+}
+
+/// Add the sample to the sketch `count` times
+///
+/// # Safety
+///
+/// `sketch` must be null or a live sketch.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_add_with_count(
+    sketch: *mut DDSketch,
+    v: f64,
+    count: u64,
+) -> DDSketchStatus {
+    match sketch.as_mut() {
+        Some(sketch) => catch(DDSketchStatus::Panic, || {
+            sketch.add_with_count(v, count);
+            DDSketchStatus::Ok
+        }),
+        None => DDSketchStatus::NullPointer,
+    }
+}
+
+/// Write the quantile `q` of the sketch to `out`. Returns `Empty` if the sketch holds no
+/// samples, in which case `out` is left untouched.
+///
+/// # Safety
+///
+/// `sketch` must be null or a live sketch, and `out` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_quantile(
+    sketch: *const DDSketch,
+    q: f64,
+    out: *mut f64,
+) -> DDSketchStatus {
+    let (sketch, out) = match (sketch.as_ref(), out.as_mut()) {
+        (Some(sketch), Some(out)) => (sketch, out),
+        _ => return DDSketchStatus::NullPointer,
+    };
+
+    catch(DDSketchStatus::Panic, || match sketch.quantile(q) {
+        Ok(Some(v)) => {
+            *out = v;
+            DDSketchStatus::Ok
+        }
+        Ok(None) => DDSketchStatus::Empty,
+        Err(e) => e.into(),
+    })
+}
+
+/// Returns the number of samples in the sketch, or 0 if `sketch` is null
+///
+/// # Safety
+///
+/// `sketch` must be null or a live sketch.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_count(sketch: *const DDSketch) -> u64 {
+    sketch
+        .as_ref()
+        .map_or(0, |sketch| catch(0, || sketch.count() as u64))
+}
+
+/// Merge `other` into `sketch`, leaving `other` unchanged. Returns `SameSketch` if they are
+/// the same sketch, which can't be merged into itself.
+///
+/// # Safety
+///
+/// Both must be null or live sketches.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_merge(
+    sketch: *mut DDSketch,
+    other: *const DDSketch,
+) -> DDSketchStatus {
+    // Checked before borrowing, as `sketch` can't be borrowed mutably while `other` is
+    if !sketch.is_null() && ptr::eq(sketch, other) {
+        return DDSketchStatus::SameSketch;
+    }
+
+    match (sketch.as_mut(), other.as_ref()) {
+        (Some(sketch), Some(other)) => catch(DDSketchStatus::Panic, || match sketch.merge(other) {
+            Ok(()) => DDSketchStatus::Ok,
+            Err(e) => e.into(),
+        }),
+        _ => DDSketchStatus::NullPointer,
+    }
+}
+
+/// Encode the sketch into `buf`, see `DDSketch::encode`. The encoded length is written to `len`
+/// in any case; if it is larger than `capacity` nothing is written to `buf` and
+/// `BufferTooSmall` is returned. `buf` may be null when `capacity` is 0, to query the length.
+///
+/// # Safety
+///
+/// `sketch` must be null or a live sketch, `buf` valid for `capacity` bytes of writes, and
+/// `len` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_encode(
+    sketch: *const DDSketch,
+    buf: *mut u8,
+    capacity: usize,
+    len: *mut usize,
+) -> DDSketchStatus {
+    let (sketch, len) = match (sketch.as_ref(), len.as_mut()) {
+        (Some(sketch), Some(len)) => (sketch, len),
+        _ => return DDSketchStatus::NullPointer,
+    };
+
+    catch(DDSketchStatus::Panic, || {
+        let encoded = sketch.encode();
+        *len = encoded.len();
+        if encoded.len() > capacity {
+            return DDSketchStatus::BufferTooSmall;
+        }
+        if buf.is_null() {
+            return DDSketchStatus::NullPointer;
+        }
+
+        ptr::copy_nonoverlapping(encoded.as_ptr(), buf, encoded.len());
+        DDSketchStatus::Ok
+    })
+}
+
+/// Decode a sketch produced by `ddsketch_encode` and store it in `out`, to be freed with
+/// `ddsketch_free`.
+///
+/// # Safety
+///
+/// `buf` must be valid for `len` bytes of reads, and `out` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ddsketch_decode(
+    buf: *const u8,
+    len: usize,
+    out: *mut *mut DDSketch,
+) -> DDSketchStatus {
+    if buf.is_null() || out.is_null() {
+        return DDSketchStatus::NullPointer;
+    }
+
+    catch(DDSketchStatus::Panic, || {
+        match DDSketch::decode(slice::from_raw_parts(buf, len)) {
+            Ok(sketch) => {
+                *out = into_handle(sketch);
+                DDSketchStatus::Ok
+            }
+            Err(e) => e.into(),
+        }
+    })
+}
+
+fn into_handle(sketch: DDSketch) -> *mut DDSketch {
+    Box::into_raw(Box::new(sketch))
+}
+
+// Run `f`, returning `on_panic` rather than unwinding across the C boundary if it panics
+fn catch<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use crate::ffi::*;
+
+    #[test]
+    fn test_lifecycle() {
+        unsafe {
+            let sketch = ddsketch_new(0.01, 2048, 1e-9);
+            assert!(!sketch.is_null());
+
+            let mut q = 0.0;
+            assert_eq!(
+                ddsketch_quantile(sketch, 0.5, &mut q),
+                DDSketchStatus::Empty
+            );
+
+            assert_eq!(ddsketch_add(sketch, 1.0), DDSketchStatus::Ok);
+            assert_eq!(ddsketch_add_with_count(sketch, 2.0, 3), DDSketchStatus::Ok);
+            assert_eq!(ddsketch_count(sketch), 4);
+
+            assert_eq!(ddsketch_quantile(sketch, 0.5, &mut q), DDSketchStatus::Ok);
+            assert!((q - 2.0).abs() < 0.02);
+            assert_eq!(
+                ddsketch_quantile(sketch, 1.5, &mut q),
+                DDSketchStatus::InvalidQuantile
+            );
+
+            let other = ddsketch_new_default();
+            ddsketch_add(other, 3.0);
+            assert_eq!(ddsketch_merge(sketch, other), DDSketchStatus::Ok);
+            assert_eq!(ddsketch_count(sketch), 5);
+            assert_eq!(ddsketch_merge(sketch, sketch), DDSketchStatus::SameSketch);
+            assert_eq!(ddsketch_count(sketch), 5);
+
+            let incompatible = ddsketch_new(0.02, 2048, 1e-9);
+            assert_eq!(
+                ddsketch_merge(sketch, incompatible),
+                DDSketchStatus::IncompatibleMerge
+            );
+
+            ddsketch_free(incompatible);
+            ddsketch_free(other);
+            ddsketch_free(sketch);
+            ddsketch_free(ptr::null_mut());
+        }
+
+        assert!(ddsketch_new(1.5, 2048, 1e-9).is_null());
+        assert!(ddsketch_new(0.01, 0, 1e-9).is_null());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        unsafe {
+            let sketch = ddsketch_new_default();
+            ddsketch_add(sketch, -1.0);
+            ddsketch_add(sketch, 5.0);
+
+            let mut len = 0;
+            assert_eq!(
+                ddsketch_encode(sketch, ptr::null_mut(), 0, &mut len),
+                DDSketchStatus::BufferTooSmall
+            );
+
+            let mut buf = vec![0u8; len];
+            assert_eq!(
+                ddsketch_encode(sketch, buf.as_mut_ptr(), buf.len(), &mut len),
+                DDSketchStatus::Ok
+            );
+            assert_eq!(buf, (*sketch).encode());
+
+            let mut decoded = ptr::null_mut();
+            assert_eq!(
+                ddsketch_decode(buf.as_ptr(), buf.len(), &mut decoded),
+                DDSketchStatus::Ok
+            );
+            assert_eq!(ddsketch_count(decoded), 2);
+            assert_eq!(
+                ddsketch_decode(buf.as_ptr(), buf.len() - 1, &mut decoded),
+                DDSketchStatus::InvalidEncoding
+            );
+
+            ddsketch_free(decoded);
+            ddsketch_free(sketch);
+        }
+    }
+
+    #[test]
+    fn test_null_pointers() {
+        unsafe {
+            let mut q = 0.0;
+            let mut len = 0;
+            let null = ptr::null_mut();
+            assert_eq!(ddsketch_add(null, 1.0), DDSketchStatus::NullPointer);
+            assert_eq!(
+                ddsketch_quantile(null, 0.5, &mut q),
+                DDSketchStatus::NullPointer
+            );
+            assert_eq!(ddsketch_merge(null, null), DDSketchStatus::NullPointer);
+            assert_eq!(
+                ddsketch_encode(null, ptr::null_mut(), 0, &mut len),
+                DDSketchStatus::NullPointer
+            );
+            assert_eq!(
+                ddsketch_decode(ptr::null(), 0, ptr::null_mut()),
+                DDSketchStatus::NullPointer
+            );
+            assert_eq!(ddsketch_count(null), 0);
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(
+            catch(DDSketchStatus::Panic, || DDSketchStatus::Ok),
+            DDSketchStatus::Ok
+        );
+        assert_eq!(
+            catch(DDSketchStatus::Panic, || panic!("unwinding into C")),
+            DDSketchStatus::Panic
+        );
+        assert!(catch(ptr::null_mut(), || -> *mut DDSketch { panic!() }).is_null());
+    }
+}
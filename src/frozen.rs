@@ -0,0 +1,203 @@
+use alloc::vec::Vec;
+
+use crate::bins::quantile_by_method;
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError, QuantileMethod};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// An immutable copy of a `DDSketch` optimized for queries, created with `DDSketch::freeze`.
+///
+/// Only the non-empty bins are kept, along with the cumulative counts up to each of them, so
+/// every quantile and rank query is a binary search rather than a scan of the bins. Queries
+/// return the same results as on the sketch it was frozen from.
+#[derive(Clone, Debug)]
+pub struct FrozenDDSketch {
+    config: Config,
+    // Bins of negative samples, in increasing value order and so decreasing key order
+    negative: Bins,
+    zero_count: u64,
+    positive: Bins,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Bins {
+    keys: Vec<i32>,
+    // Total count of the bins up to and including each one
+    cumulative: Vec<u64>,
+}
+
+impl Bins {
+    fn new(bins: impl Iterator<Item = (i32, u64)>) -> Self {
+        let mut total = 0;
+        let (keys, cumulative) = bins
+            .map(|(key, count)| {
+                total += count;
+                (key, total)
+            })
+            .unzip();
+
+        Bins { keys, cumulative }
+    }
+
+    fn count(&self) -> u64 {
+        self.cumulative.last().copied().unwrap_or(0)
+    }
+
+    // Return the key of the bin holding the sample of the given rank
+    fn key_at_rank(&self, rank: u64) -> i32 {
+        let idx = self.cumulative.partition_point(|total| *total <= rank);
+        self.keys[usize::min(idx, self.keys.len() - 1)]
+    }
+
+    // Return the total count of the bins before the first for which `after` holds
+    fn count_before(&self, after: impl Fn(i32) -> bool) -> u64 {
+        match self.keys.partition_point(|key| !after(*key)) {
+            0 => 0,
+            idx => self.cumulative[idx - 1],
+        }
+    }
+}
+
+impl DDSketch {
+    /// Returns an immutable copy of the sketch answering quantile and rank queries with a
+    /// binary search, see `FrozenDDSketch`.
+    pub fn freeze(&self) -> FrozenDDSketch {
+        let mut negative: Vec<(i32, u64)> = self.negative_bins().collect();
+        negative.reverse();
+
+        FrozenDDSketch {
+            config: *self.config(),
+            negative: Bins::new(negative.into_iter()),
+            zero_count: self.zero_count(),
+            positive: Bins::new(self.positive_bins()),
+            min: self.min().unwrap_or(f64::INFINITY),
+            max: self.max().unwrap_or(f64::NEG_INFINITY),
+            sum: self.sum().unwrap_or(0.0),
+        }
+    }
+}
+
+impl FrozenDDSketch {
+    /// Return the quantile value for quantiles between 0.0 and 1.0, see `DDSketch::quantile`
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        self.quantile_with_method(q, QuantileMethod::Lower)
+    }
+
+    /// Return the quantile value picking ranks with `method`, see
+    /// `DDSketch::quantile_with_method`
+    pub fn quantile_with_method(&self, q: f64, method: QuantileMethod) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        if self.empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(quantile_by_method(
+            q,
+            self.count(),
+            (self.min, self.max),
+            method,
+            |rank| self.value_at_rank(rank),
+        )))
+    }
+
+    /// Returns the number of samples at most `v`, see `DDSketch::rank`
+    pub fn rank(&self, v: f64) -> u64 {
+        let zero_threshold = self.config.zero_threshold();
+        let below_zero = self.negative.count() + self.zero_count;
+        if v > zero_threshold {
+            let key = self.config.key(v);
+            below_zero + self.positive.count_before(|k| k > key)
+        } else if v < -zero_threshold {
+            let key = self.config.key(-v);
+            self.negative.count_before(|k| k < key)
+        } else {
+            below_zero
+        }
+    }
+
+    /// Returns the minimum value seen, or None if sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        self.if_not_empty(self.min)
+    }
+
+    /// Returns the maximum value seen, or None if sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        self.if_not_empty(self.max)
+    }
+
+    /// Returns the sum of values seen, or None if sketch is empty
+    pub fn sum(&self) -> Option<f64> {
+        self.if_not_empty(self.sum)
+    }
+
+    /// Returns the number of values added to the sketch
+    pub fn count(&self) -> u64 {
+        self.negative.count() + self.zero_count + self.positive.count()
+    }
+
+    /// Returns the config of the sketch
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    fn if_not_empty(&self, v: f64) -> Option<f64> {
+        if self.empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+
+    fn value_at_rank(&self, rank: u64) -> f64 {
+        let negative_count = self.negative.count();
+        if rank < negative_count {
+            -self.config.value(self.negative.key_at_rank(rank))
+        } else if rank < negative_count + self.zero_count {
+            0.0
+        } else {
+            let rank = rank - negative_count - self.zero_count;
+            self.config.value(self.positive.key_at_rank(rank))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{assert_same_quantiles, random_sketches};
+    use crate::DDSketch;
+
+    #[test]
+    fn test_same_as_sketch() {
+        assert_eq!(DDSketch::default().freeze().quantile(0.5).unwrap(), None);
+
+        for dd in random_sketches(42) {
+            let frozen = dd.freeze();
+            assert_eq!(frozen.count(), dd.count() as u64);
+            assert_eq!(frozen.min(), dd.min());
+            assert_eq!(frozen.max(), dd.max());
+            assert_eq!(frozen.sum(), dd.sum());
+            assert_eq!(frozen.quantile(0.0).unwrap(), dd.quantile(0.0).unwrap());
+            assert_eq!(frozen.quantile(1.0).unwrap(), dd.quantile(1.0).unwrap());
+            assert_same_quantiles(
+                |q, method| frozen.quantile_with_method(q, method),
+                |q, method| dd.quantile_with_method(q, method),
+            );
+
+            for v in [-2e3, -500.0, -1.0, 0.0, 1.0, 50.0, 1e4, 2e5] {
+                assert_eq!(frozen.rank(v), dd.rank(v), "rank of {}", v);
+            }
+            assert!(frozen.quantile(1.5).is_err());
+        }
+    }
+}
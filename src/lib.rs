@@ -41,13 +41,47 @@ d2.add(2.0);
 d1.merge(&d2);
 
 assert_eq!(d1.count(), 3);
+```
+
+# `no_std`
+
+The crate only needs `alloc`. Disable the default `std` feature and enable the `libm` feature,
+which provides the logarithm and exponential functions otherwise taken from `std`.
+
+```toml
+sketches-ddsketch = { version = "0.3", default-features = false, features = ["libm"] }
 ```
 
  */
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-pub use self::config::Config;
-pub use self::ddsketch::{DDSketch, DDSketchError};
+extern crate alloc;
 
+pub use self::compare::TestResult;
+pub use self::config::Config;
+pub use self::ddsketch::{Accuracy, DDSketch, DDSketchError, QuantileMethod};
+pub use self::frozen::FrozenDDSketch;
+pub use self::light::LightDDSketch;
+pub use self::mapping::KeyMapping;
+#[cfg(feature = "std")]
+pub use self::sketch_map::SketchMap;
+pub use self::store::CollapseDirection;
+
+mod bins;
+mod compare;
 mod config;
 mod ddsketch;
+mod encoding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod frozen;
+mod light;
+mod mapping;
+mod math;
+mod moments;
+mod render;
+#[cfg(feature = "std")]
+mod sketch_map;
 mod store;
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,266 @@
+use crate::bins::Bins;
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError, QuantileMethod};
+
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
+
+type Result<T> = core::result::Result<T, DDSketchError>;
+
+/// A `DDSketch` keeping only its bins, without exact summary statistics, as the base
+/// `DDSketch` of the Go implementation does. `min`, `max` and `sum` are estimated from the
+/// bins, so they are only as accurate as quantiles are, but the sketch is smaller and
+/// cheaper to update. `DDSketch` matches Go's `DDSketchWithExactSummaryStatistics`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "use_serde", serde(transparent))]
+pub struct LightDDSketch {
+    bins: Bins,
+}
+
+impl Default for LightDDSketch {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl LightDDSketch {
+    /// Construct a `LightDDSketch`. Requires a `Config` specifying the parameters of the sketch
+    pub fn new(config: Config) -> Self {
+        LightDDSketch {
+            bins: Bins::new(config),
+        }
+    }
+
+    /// Add the sample to the sketch
+    pub fn add(&mut self, v: f64) {
+        self.add_with_count(v, 1);
+    }
+
+    /// Add the sample to the sketch `count` times
+    pub fn add_with_count(&mut self, v: f64, count: u64) {
+        if count == 0 {
+            return;
+        }
+
+        self.bins.add(v, count);
+    }
+
+    /// Return the quantile value for quantiles between 0.0 and 1.0, see `DDSketch::quantile`.
+    /// Quantiles 0.0 and 1.0 are the estimated `min` and `max`.
+    pub fn quantile(&self, q: f64) -> Result<Option<f64>> {
+        self.quantile_with_method(q, QuantileMethod::Lower)
+    }
+
+    /// Return the quantile value picking ranks with `method`, see
+    /// `DDSketch::quantile_with_method`
+    pub fn quantile_with_method(&self, q: f64, method: QuantileMethod) -> Result<Option<f64>> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(DDSketchError::Quantile);
+        }
+
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(self.bins.quantile(q, (min, max), method)))
+    }
+
+    /// Returns the value of the lowest non-empty bin, or None if sketch is empty
+    pub fn min(&self) -> Option<f64> {
+        let Bins {
+            config,
+            store,
+            negative_store,
+            zero_count,
+        } = &self.bins;
+        if let Some((_, max_key)) = negative_store.key_range() {
+            Some(-config.value(max_key))
+        } else if *zero_count > 0 {
+            Some(0.0)
+        } else {
+            let (min_key, _) = store.key_range()?;
+            Some(config.value(min_key))
+        }
+    }
+
+    /// Returns the value of the highest non-empty bin, or None if sketch is empty
+    pub fn max(&self) -> Option<f64> {
+        let Bins {
+            config,
+            store,
+            negative_store,
+            zero_count,
+        } = &self.bins;
+        if let Some((_, max_key)) = store.key_range() {
+            Some(config.value(max_key))
+        } else if *zero_count > 0 {
+            Some(0.0)
+        } else {
+            let (min_key, _) = negative_store.key_range()?;
+            Some(-config.value(min_key))
+        }
+    }
+
+    /// Returns the sum of the values of the bins weighted by their counts, or None if sketch is
+    /// empty
+    pub fn sum(&self) -> Option<f64> {
+        if self.count() == 0 {
+            return None;
+        }
+
+        let config = &self.bins.config;
+        let positive: f64 = self
+            .positive_bins()
+            .map(|(key, count)| config.value(key) * count as f64)
+            .sum();
+        let negative: f64 = self
+            .negative_bins()
+            .map(|(key, count)| config.value(key) * count as f64)
+            .sum();
+        Some(positive - negative)
+    }
+
+    /// Returns the number of values added to the sketch
+    pub fn count(&self) -> usize {
+        self.bins.count() as usize
+    }
+
+    /// Returns the configuration of the sketch, see `DDSketch::config`
+    pub fn config(&self) -> &Config {
+        &self.bins.config
+    }
+
+    /// Returns the number of samples too close to zero to be mapped to a bin
+    pub fn zero_count(&self) -> u64 {
+        self.bins.zero_count
+    }
+
+    /// Returns an iterator over the keys and counts of the non-empty bins holding positive
+    /// samples, in key order.
+    pub fn positive_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.bins.store.bins()
+    }
+
+    /// Returns an iterator over the keys and counts of the non-empty bins holding negative
+    /// samples, in key order. Keys are those of the absolute values.
+    pub fn negative_bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        self.bins.negative_store.bins()
+    }
+
+    /// Returns true if either store has collapsed its bins to stay within its bin limit
+    pub fn is_collapsed(&self) -> bool {
+        self.bins.is_collapsed()
+    }
+
+    /// Returns the relative accuracy currently guaranteed for every quantile, see
+    /// `DDSketch::alpha`
+    pub fn alpha(&self) -> f64 {
+        self.bins.config.alpha()
+    }
+
+    /// Returns the length of the underlying stores, see `DDSketch::length`
+    pub fn length(&self) -> usize {
+        self.bins.length()
+    }
+
+    /// Merge the contents of another sketch into this one, see `DDSketch::merge`
+    pub fn merge(&mut self, o: &LightDDSketch) -> Result<()> {
+        self.bins.merge(&o.bins)
+    }
+}
+
+impl From<&DDSketch> for LightDDSketch {
+    /// Drop the exact summary statistics of the sketch, keeping its bins
+    fn from(sketch: &DDSketch) -> Self {
+        LightDDSketch {
+            bins: sketch.bins().clone(),
+        }
+    }
+}
+
+impl From<&LightDDSketch> for DDSketch {
+    /// Build a `DDSketch` from the bins of the sketch, with summary statistics estimated from
+    /// them, see `DDSketch::from_bins`
+    fn from(sketch: &LightDDSketch) -> Self {
+        DDSketch::from_bins(
+            *sketch.config(),
+            sketch.positive_bins(),
+            sketch.negative_bins(),
+            sketch.zero_count(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{assert_same_quantiles, configs, random_values};
+    use crate::{Config, DDSketch, LightDDSketch};
+
+    #[test]
+    fn test_same_bins_as_sketch() {
+        let values = random_values(42);
+        for config in configs() {
+            let mut dd = DDSketch::new(config);
+            let mut light = LightDDSketch::new(config);
+            assert_eq!(light.quantile(0.5).unwrap(), None);
+            assert_eq!(light.min(), None);
+            assert_eq!(light.sum(), None);
+
+            for v in &values {
+                dd.add(*v);
+                light.add(*v);
+            }
+            assert_eq!(light.count(), dd.count());
+            assert_eq!(light.config(), dd.config());
+            assert!(light.positive_bins().eq(dd.positive_bins()));
+            assert!(light.negative_bins().eq(dd.negative_bins()));
+            assert_same_quantiles(
+                |q, method| light.quantile_with_method(q, method),
+                |q, method| dd.quantile_with_method(q, method),
+            );
+
+            assert_eq!(light.min(), light.quantile(0.0).unwrap());
+            assert_eq!(light.max(), light.quantile(1.0).unwrap());
+            if !dd.is_collapsed() {
+                let alpha = dd.alpha();
+                assert!((light.min().unwrap() / dd.min().unwrap() - 1.0).abs() <= alpha);
+                assert!((light.max().unwrap() / dd.max().unwrap() - 1.0).abs() <= alpha);
+                assert!((light.sum().unwrap() / dd.sum().unwrap() - 1.0).abs() <= alpha);
+            }
+
+            let converted = LightDDSketch::from(&dd);
+            assert!(converted.positive_bins().eq(light.positive_bins()));
+            let back = DDSketch::from(&light);
+            assert_eq!(back.count(), dd.count());
+            assert_eq!(back.min(), light.min());
+            assert_eq!(back.max(), light.max());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let config = Config::uniform_collapsing(0.01, 128, 1e-9);
+        let mut light1 = LightDDSketch::new(config);
+        let mut light2 = LightDDSketch::new(config);
+        let mut dd1 = DDSketch::new(config);
+        let mut dd2 = DDSketch::new(config);
+        for i in 1..=1000 {
+            light1.add(i as f64);
+            dd1.add(i as f64);
+            light2.add(-1e6 * i as f64);
+            dd2.add(-1e6 * i as f64);
+        }
+
+        light1.merge(&light2).unwrap();
+        dd1.merge(&dd2).unwrap();
+        assert_eq!(light1.config(), dd1.config());
+        assert!(light1.positive_bins().eq(dd1.positive_bins()));
+        assert!(light1.negative_bins().eq(dd1.negative_bins()));
+
+        assert!(light1
+            .merge(&LightDDSketch::new(Config::defaults()))
+            .is_err());
+    }
+}
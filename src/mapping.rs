@@ -0,0 +1,103 @@
+// Approximations of the base 2 logarithm and its inverse, computed from the IEEE-754 exponent
+// and significand, as in the `CubicallyInterpolatedMapping` of sketches-go.
+
+use core::f64::consts::LN_2;
+
+use crate::math;
+
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
+
+/// How a `Config` maps values to the keys of their bins, see `Config::with_mapping`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+pub enum KeyMapping {
+    /// Keys from the natural logarithm of the value, which uses the fewest bins for the
+    /// relative accuracy
+    #[default]
+    Logarithmic,
+    /// Keys from a cubic approximation of the logarithm, built from the exponent and
+    /// significand of the value. This avoids computing a logarithm for every sample, at the
+    /// cost of about 1% more bins for the same relative accuracy.
+    CubicInterpolated,
+}
+
+// Coefficients of the cubic approximating log2(1 + s) for s in [0, 1)
+const A: f64 = 6.0 / 35.0;
+const B: f64 = -3.0 / 5.0;
+const C: f64 = 10.0 / 7.0;
+
+/// The smallest slope of `approx_log2` against the natural logarithm, reached at powers of 2.
+/// Dividing by it keeps the bins no wider than those of the natural logarithm.
+pub const MIN_SLOPE: f64 = C;
+
+const SIGNIFICAND_MASK: u64 = (1 << 52) - 1;
+const EXPONENT_BIAS: i64 = 1023;
+
+/// Approximate `log2(v)` for positive `v`. It is exact at powers of 2, and increasing.
+pub fn approx_log2(v: f64) -> f64 {
+    let bits = v.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    if exponent == 0 {
+        // Subnormal values have no implicit leading bit
+        return math::ln(v) / LN_2;
+    }
+
+    let s = f64::from_bits((bits & SIGNIFICAND_MASK) | ((EXPONENT_BIAS as u64) << 52)) - 1.0;
+    ((A * s + B) * s + C) * s + (exponent - EXPONENT_BIAS) as f64
+}
+
+/// The inverse of `approx_log2`, solving the cubic with Cardano's formula
+pub fn approx_exp2(x: f64) -> f64 {
+    let exponent = math::floor(x);
+    let d0 = B * B - 3.0 * A * C;
+    let d1 = 2.0 * B * B * B - 9.0 * A * B * C - 27.0 * A * A * (x - exponent);
+    let p = math::cbrt((d1 - math::sqrt(d1 * d1 - 4.0 * d0 * d0 * d0)) / 2.0);
+    let significand = -(B + p + d0 / p) / (3.0 * A) + 1.0;
+
+    significand * math::powi(2.0, exponent as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::mapping::{approx_exp2, approx_log2, KeyMapping};
+    use crate::Config;
+
+    #[test]
+    fn test_approx_log2() {
+        for v in [1.0, 2.0, 0.25, 1024.0] {
+            assert_eq!(approx_log2(v), v.log2());
+        }
+        for v in [1e-300, 1e-9, 0.3, 1.0, 1.5, 3.7, 1e9, 1e300] {
+            assert!((approx_log2(v) - v.log2()).abs() < 0.01);
+            assert_relative_eq!(approx_exp2(approx_log2(v)), v, max_relative = 1e-12);
+        }
+        assert!(approx_log2(f64::MIN_POSITIVE / 4.0) < approx_log2(f64::MIN_POSITIVE));
+    }
+
+    #[test]
+    fn test_relative_accuracy() {
+        for alpha in [0.001, 0.01, 0.05] {
+            for mapping in [KeyMapping::Logarithmic, KeyMapping::CubicInterpolated] {
+                let config = Config::new(alpha, 2048, 1e-9).with_mapping(mapping);
+
+                // Sweep densely across 18 orders of magnitude
+                let mut v = 1e-9;
+                while v < 1e9 {
+                    let actual = config.value(config.key(v));
+                    assert!(
+                        (actual - v).abs() <= alpha * v * (1.0 + 1e-9),
+                        "{:?} alpha={} v={} value={}",
+                        mapping,
+                        alpha,
+                        v,
+                        actual
+                    );
+                    v *= 1.0001;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Floating point functions missing from `core`, taken from `std` when available and from
+// `libm` otherwise.
+
+#[cfg(feature = "std")]
+mod imp {
+    pub fn ln(x: f64) -> f64 {
+        x.ln()
+    }
+
+    pub fn ln_1p(x: f64) -> f64 {
+        x.ln_1p()
+    }
+
+    pub fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+
+    pub fn ceil(x: f64) -> f64 {
+        x.ceil()
+    }
+
+    pub fn powi(x: f64, n: i32) -> f64 {
+        x.powi(n)
+    }
+
+    pub fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+
+    pub fn cbrt(x: f64) -> f64 {
+        x.cbrt()
+    }
+
+    pub fn floor(x: f64) -> f64 {
+        x.floor()
+    }
+}
+
+#[cfg(all(not(feature = "std"), feature = "libm"))]
+mod imp {
+    pub fn ln(x: f64) -> f64 {
+        libm::log(x)
+    }
+
+    pub fn ln_1p(x: f64) -> f64 {
+        libm::log1p(x)
+    }
+
+    pub fn exp(x: f64) -> f64 {
+        libm::exp(x)
+    }
+
+    pub fn ceil(x: f64) -> f64 {
+        libm::ceil(x)
+    }
+
+    pub fn powi(x: f64, n: i32) -> f64 {
+        libm::pow(x, n as f64)
+    }
+
+    pub fn sqrt(x: f64) -> f64 {
+        libm::sqrt(x)
+    }
+
+    pub fn cbrt(x: f64) -> f64 {
+        libm::cbrt(x)
+    }
+
+    pub fn floor(x: f64) -> f64 {
+        libm::floor(x)
+    }
+}
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("sketches-ddsketch requires either the `std` or the `libm` feature");
+
+pub use self::imp::*;
+
+/// Complementary error function, which neither `core` nor `std` provide. This is the Chebyshev
+/// approximation from Numerical Recipes, with a fractional error below 1.2e-7.
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let r = t * exp(-z * z + poly);
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::math::erfc;
+
+    #[test]
+    fn test_erfc() {
+        assert_relative_eq!(erfc(0.0), 1.0, max_relative = 1.2e-7);
+        assert_relative_eq!(erfc(1.0), 0.157_299_207_050_285_1, max_relative = 1.2e-7);
+        assert_relative_eq!(erfc(3.0), 2.209_049_699_858_544e-5, max_relative = 1.2e-7);
+        assert_relative_eq!(erfc(-1.0), 1.842_700_792_949_715, max_relative = 1.2e-7);
+    }
+}
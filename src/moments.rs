@@ -0,0 +1,141 @@
+use alloc::vec::Vec;
+
+use crate::ddsketch::DDSketchError;
+use crate::encoding::{self, Reader};
+use crate::math;
+
+#[cfg(feature = "use_serde")]
+use serde::{Deserialize, Serialize};
+
+/// Running mean and central moments of the samples, combined with the parallel algorithm of
+/// [Pébay](https://www.osti.gov/biblio/1028931) so they stay mergeable. Unlike quantiles, which
+/// are estimated from the bins, these are exact up to floating point error.
+///
+/// The moments count their samples, so a sketch can tell whether they cover all of its own,
+/// which they don't for sketches serialized before moments were tracked.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+pub struct Moments {
+    mean: f64,
+    m2: f64,
+    m3: f64,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    count: u64,
+}
+
+impl Moments {
+    /// Add `count` samples of value `v`
+    pub fn add(&mut self, v: f64, count: u64) {
+        let o = Moments {
+            mean: v,
+            m2: 0.0,
+            m3: 0.0,
+            count,
+        };
+        self.merge(&o);
+    }
+
+    /// Merge the moments of other samples into these
+    pub fn merge(&mut self, o: &Moments) {
+        if o.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *o;
+            return;
+        }
+
+        let (n_a, n_b) = (self.count as f64, o.count as f64);
+        let delta = o.mean - self.mean;
+        let delta_n = delta / (n_a + n_b);
+
+        self.m3 += o.m3
+            + delta * delta_n * delta_n * n_a * n_b * (n_a - n_b)
+            + 3.0 * delta_n * (n_a * o.m2 - n_b * self.m2);
+        self.m2 += o.m2 + delta * delta_n * n_a * n_b;
+        self.mean += delta_n * n_b;
+        self.count += o.count;
+    }
+
+    /// Return the number of samples
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Return the population variance
+    pub fn variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// Return the population skewness, or 0.0 if the samples are all equal
+    pub fn skewness(&self) -> f64 {
+        if self.m2 == 0.0 {
+            return 0.0;
+        }
+
+        math::sqrt(self.count as f64) * self.m3 / (self.m2 * math::sqrt(self.m2))
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        encoding::put_f64(buf, self.mean);
+        encoding::put_f64(buf, self.m2);
+        encoding::put_f64(buf, self.m3);
+        encoding::put_u64(buf, self.count);
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, DDSketchError> {
+        Ok(Moments {
+            mean: r.f64()?,
+            m2: r.f64()?,
+            m3: r.f64()?,
+            count: r.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::moments::Moments;
+
+    #[test]
+    fn test_merge() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0, 30.0];
+
+        let mut all = Moments::default();
+        for v in values {
+            all.add(v, 1);
+        }
+
+        let mut a = Moments::default();
+        let mut b = Moments::default();
+        for v in &values[..4] {
+            a.add(*v, 1);
+        }
+        for v in &values[4..] {
+            b.add(*v, 1);
+        }
+        a.merge(&b);
+
+        let n = values.len() as u64;
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let central = |p: i32| values.iter().map(|v| (v - mean).powi(p)).sum::<f64>() / n as f64;
+
+        for m in [all, a] {
+            assert_relative_eq!(m.mean, mean, max_relative = 1e-12);
+            assert_eq!(m.count(), n);
+            assert_relative_eq!(m.variance(), central(2), max_relative = 1e-12);
+            assert_relative_eq!(
+                m.skewness(),
+                central(3) / central(2).powf(1.5),
+                max_relative = 1e-12
+            );
+        }
+
+        let mut constant = Moments::default();
+        constant.add(3.0, 10);
+        assert_eq!(constant.variance(), 0.0);
+        assert_eq!(constant.skewness(), 0.0);
+    }
+}
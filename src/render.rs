@@ -0,0 +1,195 @@
+//! Text histograms of sketches, for eyeballing a distribution in a terminal.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::ddsketch::DDSketch;
+
+// Quantiles marked next to the row holding them
+const MARKERS: [(f64, &str); 2] = [(0.5, "p50"), (0.99, "p99")];
+
+struct Row {
+    label: String,
+    count: u64,
+}
+
+impl DDSketch {
+    /// Render the sketch as a histogram of about `rows` rows, in increasing value order. Rows
+    /// group adjacent bins, so they are log-spaced, and are labelled with the range of values
+    /// they cover. Bars are up to `width` characters long, in proportion to the count of the
+    /// row, and the rows holding the median and the 99th percentile are marked.
+    ///
+    /// The rows are split between negative values, zero and positive values, and each present
+    /// gets at least one, so the histogram may have more than `rows` rows. Returns an empty
+    /// string if the sketch is empty.
+    pub fn render_ascii(&self, width: usize, rows: usize) -> String {
+        let count = self.count() as u64;
+        if count == 0 {
+            return String::new();
+        }
+
+        let config = self.config();
+        let negative: Vec<(i32, u64)> = self.negative_bins().collect();
+        let positive: Vec<(i32, u64)> = self.positive_bins().collect();
+
+        // Split the rows in proportion to the keys spanned by each side
+        let (negative_span, positive_span) = (span(&negative), span(&positive));
+        let available = rows.saturating_sub((self.zero_count() > 0) as usize);
+        let negative_rows = if negative_span == 0 {
+            0
+        } else {
+            let share = available * negative_span / (negative_span + positive_span);
+            share.clamp(1, negative_span)
+        };
+        let positive_rows = if positive_span == 0 {
+            0
+        } else {
+            available
+                .saturating_sub(negative_rows)
+                .clamp(1, positive_span)
+        };
+
+        let mut table = Vec::new();
+        for (min_key, max_key, count) in group(&negative, negative_rows).into_iter().rev() {
+            table.push(Row {
+                label: format!(
+                    "[{}, {})",
+                    format_value(-config.pow_gamma(max_key)),
+                    format_value(-config.pow_gamma(min_key - 1))
+                ),
+                count,
+            });
+        }
+        if self.zero_count() > 0 {
+            table.push(Row {
+                label: String::from("0"),
+                count: self.zero_count(),
+            });
+        }
+        for (min_key, max_key, count) in group(&positive, positive_rows) {
+            table.push(Row {
+                label: format!(
+                    "({}, {}]",
+                    format_value(config.pow_gamma(min_key - 1)),
+                    format_value(config.pow_gamma(max_key))
+                ),
+                count,
+            });
+        }
+
+        let label_width = table.iter().map(|row| row.label.len()).max().unwrap_or(0);
+        let max_count = table.iter().map(|row| row.count).max().unwrap_or(0);
+
+        let mut out = String::new();
+        let mut cumulative = 0;
+        for row in &table {
+            let mut bar = ((row.count as u128 * width as u128 + max_count as u128 / 2)
+                / max_count as u128) as usize;
+            if row.count > 0 {
+                bar = bar.max(1);
+            }
+
+            // Same ranks as `quantile`
+            let before = cumulative;
+            cumulative += row.count;
+            let markers: Vec<&str> = MARKERS
+                .iter()
+                .filter(|(q, _)| {
+                    let rank = (q * (count as f64 - 1.0)) as u64;
+                    before <= rank && rank < cumulative
+                })
+                .map(|(_, label)| *label)
+                .collect();
+
+            let line = format!(
+                "{:>label_width$} | {:<width$} {} {}",
+                row.label,
+                "#".repeat(bar),
+                row.count,
+                markers.join(" "),
+                label_width = label_width,
+                width = width
+            );
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+
+        out
+    }
+}
+
+fn span(bins: &[(i32, u64)]) -> usize {
+    match (bins.first(), bins.last()) {
+        (Some(first), Some(last)) => (last.0 - first.0 + 1) as usize,
+        _ => 0,
+    }
+}
+
+// Group the bins into `rows` ranges of adjacent keys, as (min key, max key, count)
+fn group(bins: &[(i32, u64)], rows: usize) -> Vec<(i32, i32, u64)> {
+    let (first, span) = match bins.first() {
+        Some((key, _)) => (*key as i64, span(bins) as i64),
+        None => return Vec::new(),
+    };
+
+    let rows = rows as i64;
+    let mut groups: Vec<(i32, i32, u64)> = (0..rows)
+        .map(|i| {
+            let min_key = first + i * span / rows;
+            let max_key = first + (i + 1) * span / rows - 1;
+            (min_key as i32, max_key as i32, 0)
+        })
+        .collect();
+    for (key, count) in bins {
+        let row = ((*key as i64 - first) * rows / span) as usize;
+        groups[row].2 += count;
+    }
+
+    groups
+}
+
+fn format_value(v: f64) -> String {
+    if v == 0.0 || (1e-3..1e6).contains(&v.abs()) {
+        format!("{:.3}", v)
+    } else {
+        format!("{:.3e}", v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DDSketch;
+
+    #[test]
+    fn test_render_ascii() {
+        let mut dd = DDSketch::default();
+        assert_eq!(dd.render_ascii(20, 10), "");
+
+        for i in 1..=100 {
+            dd.add(i as f64);
+        }
+        assert_eq!(
+            dd.render_ascii(20, 4),
+            "   (0.980, 3.127] | #                    3
+   (3.127, 9.975] | ##                   6
+  (9.975, 31.821] | ######               22
+(31.821, 101.510] | #################### 69 p50 p99
+"
+        );
+
+        let mut dd = DDSketch::default();
+        dd.add_with_count(-1000.0, 2);
+        dd.add_with_count(-1.0, 2);
+        dd.add_with_count(0.0, 4);
+        dd.add_with_count(5.0, 8);
+        assert_eq!(
+            dd.render_ascii(8, 4),
+            "[-1012.554, -31.191) | ##       2
+   [-31.191, -0.980) | ##       2
+                   0 | ####     4 p50
+      (4.953, 5.053] | ######## 8 p99
+"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Borrow;
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::config::Config;
+use crate::ddsketch::{DDSketch, DDSketchError};
+
+type Result<T> = std::result::Result<T, DDSketchError>;
+
+/// A collection of sketches sharing one `Config`, keyed by labels such as an endpoint and status.
+///
+/// Sketches are created the first time a key is seen. The number of keys can be capped, after
+/// which samples for new keys are added to a single overflow sketch instead, so an explosion of
+/// labels can not grow the collection without bound.
+#[derive(Clone)]
+pub struct SketchMap<K> {
+    config: Config,
+    sketches: HashMap<K, DDSketch>,
+    max_keys: Option<usize>,
+    overflow: DDSketch,
+}
+
+impl<K: Eq + Hash> SketchMap<K> {
+    /// Construct a `SketchMap` without a limit on the number of keys
+    pub fn new(config: Config) -> Self {
+        SketchMap {
+            config,
+            sketches: HashMap::new(),
+            max_keys: None,
+            overflow: DDSketch::new(config),
+        }
+    }
+
+    /// Construct a `SketchMap` holding at most `max_keys` keys. Samples for any further keys are
+    /// added to the overflow sketch.
+    pub fn with_max_keys(config: Config, max_keys: usize) -> Self {
+        SketchMap {
+            max_keys: Some(max_keys),
+            ..Self::new(config)
+        }
+    }
+
+    /// Add the sample to the sketch for `key`, creating it if needed
+    pub fn add(&mut self, key: K, v: f64) {
+        if let Some(sketch) = self.sketches.get_mut(&key) {
+            sketch.add(v);
+        } else if self.is_full() {
+            self.overflow.add(v);
+        } else {
+            let mut sketch = DDSketch::new(self.config);
+            sketch.add(v);
+            self.sketches.insert(key, sketch);
+        }
+    }
+
+    /// Returns the sketch for `key`, or None if no samples were added for it
+    pub fn get<Q>(&self, key: &Q) -> Option<&DDSketch>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.sketches.get(key)
+    }
+
+    /// Returns the sketch holding the samples of keys beyond the limit
+    pub fn overflow(&self) -> &DDSketch {
+        &self.overflow
+    }
+
+    /// Returns an iterator over the keys and their sketches, in arbitrary order. The overflow
+    /// sketch is not included.
+    pub fn iter(&self) -> hash_map::Iter<'_, K, DDSketch> {
+        self.sketches.iter()
+    }
+
+    /// Returns the number of keys, not counting the overflow sketch
+    pub fn len(&self) -> usize {
+        self.sketches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sketches.is_empty()
+    }
+
+    /// Merge the contents of another map into this one, key by key. Keys new to this map are
+    /// subject to its limit, and the overflow sketches are merged together.
+    pub fn merge(&mut self, o: &SketchMap<K>) -> Result<()>
+    where
+        K: Clone,
+    {
+        if self.config != o.config {
+            return Err(DDSketchError::Merge);
+        }
+
+        for (key, sketch) in &o.sketches {
+            if let Some(s) = self.sketches.get_mut(key) {
+                s.merge(sketch)?;
+            } else if self.is_full() {
+                self.overflow.merge(sketch)?;
+            } else {
+                self.sketches.insert(key.clone(), sketch.clone());
+            }
+        }
+        self.overflow.merge(&o.overflow)
+    }
+
+    fn is_full(&self) -> bool {
+        self.max_keys
+            .is_some_and(|max_keys| self.sketches.len() >= max_keys)
+    }
+}
+
+impl<'a, K> IntoIterator for &'a SketchMap<K> {
+    type Item = (&'a K, &'a DDSketch);
+    type IntoIter = hash_map::Iter<'a, K, DDSketch>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.sketches.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Config;
+    use crate::SketchMap;
+
+    #[test]
+    fn test_add() {
+        let mut m = SketchMap::new(Config::defaults());
+        assert!(m.is_empty());
+
+        m.add(("/home", 200), 1.0);
+        m.add(("/home", 200), 2.0);
+        m.add(("/home", 500), 3.0);
+
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.get(&("/home", 200)).unwrap().count(), 2);
+        assert_eq!(m.get(&("/home", 500)).unwrap().max(), Some(3.0));
+        assert!(m.get(&("/about", 200)).is_none());
+        assert_eq!(m.iter().map(|(_, s)| s.count()).sum::<usize>(), 3);
+        assert_eq!(m.overflow().count(), 0);
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let mut m = SketchMap::with_max_keys(Config::defaults(), 2);
+        for i in 0..10 {
+            m.add(i % 5, i as f64);
+        }
+
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.get(&0).unwrap().count(), 2);
+        assert_eq!(m.get(&1).unwrap().count(), 2);
+        assert_eq!(m.overflow().count(), 6);
+    }
+
+    #[test]
+    fn test_merge() {
+        let c = Config::defaults();
+        let mut m1 = SketchMap::with_max_keys(c, 2);
+        let mut m2 = SketchMap::new(c);
+
+        m1.add("a", 1.0);
+        m2.add("a", 2.0);
+        m2.add("b", 3.0);
+        m2.add("c", 4.0);
+
+        m1.merge(&m2).unwrap();
+        assert_eq!(m1.len(), 2);
+        assert_eq!(m1.get("a").unwrap().count(), 2);
+        assert_eq!(m1.overflow().count(), 1);
+        assert_eq!((&m1).into_iter().count(), 2);
+
+        let m3 = SketchMap::new(Config::new(0.02, 2048, 1e-9));
+        assert!(m1.merge(&m3).is_err());
+    }
+}
@@ -1,8 +1,19 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::ddsketch::DDSketchError;
+use crate::encoding::{self, Reader};
+
 #[cfg(feature = "use_serde")]
 use serde::{Deserialize, Serialize};
 
 const CHUNK_SIZE: i32 = 128;
 
+/// The largest absolute key a store can hold. The range between any two keys, rounded up to
+/// whole chunks of bins, must fit in an `i32`.
+pub(crate) const MAX_KEY: i32 = i32::MAX / 4;
+
 // Divide the `dividend` by the `divisor`, rounding towards positive infinity.
 //
 // Similar to the nightly only `std::i32::div_ceil`.
@@ -10,7 +21,31 @@ fn div_ceil(dividend: i32, divisor: i32) -> i32 {
     (dividend + divisor - 1) / divisor
 }
 
-/// CollapsingLowestDenseStore
+/// Return the key that `key` maps to once adjacent bins have been collapsed uniformly, which is
+/// `ceil(key / 2)`.
+pub fn collapse_key(key: i32) -> i32 {
+    fold_key(key, 2)
+}
+
+/// Return the key that `key` maps to once every `factor` adjacent bins have been folded into
+/// one, which is `ceil(key / factor)`.
+pub fn fold_key(key: i32, factor: i32) -> i32 {
+    (key + factor - 1).div_euclid(factor)
+}
+
+/// Which bins a store merges once it reaches its bin limit, giving up the accuracy of the
+/// quantiles at that end of its keys
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+pub enum CollapseDirection {
+    /// Merge the bins of the lowest keys, those of the values closest to zero
+    #[default]
+    Lowest,
+    /// Merge the bins of the highest keys, those of the values furthest from zero
+    Highest,
+}
+
+/// CollapsingLowestDenseStore, or CollapsingHighestDenseStore by negating the keys of its bins
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 pub struct Store {
@@ -21,6 +56,8 @@ pub struct Store {
     offset: i32,
     bin_limit: usize,
     is_collapsed: bool,
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    direction: CollapseDirection,
 }
 
 impl Store {
@@ -33,6 +70,23 @@ impl Store {
             offset: 0,
             bin_limit,
             is_collapsed: false,
+            direction: CollapseDirection::Lowest,
+        }
+    }
+
+    /// Return the store collapsing its bins in `direction` once it reaches its bin limit.
+    pub fn with_direction(mut self, direction: CollapseDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    // Map a key to the key of its bin and back. Bins always collapse their lowest keys, so the
+    // keys are negated to collapse the highest ones. Other than in the public methods, keys are
+    // those of the bins.
+    fn bin_key(&self, key: i32) -> i32 {
+        match self.direction {
+            CollapseDirection::Lowest => key,
+            CollapseDirection::Highest => -key,
         }
     }
 
@@ -45,10 +99,39 @@ impl Store {
         self.bins.is_empty()
     }
 
-    pub fn add(&mut self, key: i32) {
-        let idx = self.get_index(key);
-        self.bins[idx] += 1;
-        self.count += 1;
+    pub fn add_count(&mut self, key: i32, count: u64) {
+        let idx = self.get_index(self.bin_key(key));
+        self.bins[idx] += count;
+        self.count += count;
+    }
+
+    /// Add a sample to the bin of each key. The bins are grown once to cover all of the keys,
+    /// rather than checking the range for every key, with the same result as adding them one
+    /// at a time.
+    pub fn add_keys(&mut self, keys: &[i32]) {
+        let (mut min_key, max_key) = match keys.iter().fold(None, |range, key| {
+            let key = self.bin_key(*key);
+            Some(range.map_or((key, key), |(lo, hi)| {
+                (i32::min(lo, key), i32::max(hi, key))
+            }))
+        }) {
+            Some(range) => range,
+            None => return,
+        };
+
+        // Once collapsed, lower keys go to the lowest bin without growing the bins
+        if self.is_collapsed {
+            min_key = i32::max(min_key, self.min_key);
+        }
+        if min_key < self.min_key || max_key > self.max_key {
+            self.extend_range(min_key, Some(max_key));
+        }
+
+        for key in keys {
+            let idx = (i32::max(self.bin_key(*key), self.min_key) - self.offset) as usize;
+            self.bins[idx] += 1;
+        }
+        self.count += keys.len() as u64;
     }
 
     fn get_index(&mut self, key: i32) -> usize {
@@ -75,7 +158,7 @@ impl Store {
 
         if self.is_empty() {
             let new_len = self.get_new_length(new_min_key, new_max_key);
-            self.bins.resize(new_len, 0);
+            self.grow(new_len);
             self.offset = new_min_key;
             self.adjust(new_min_key, new_max_key);
         } else if new_min_key >= self.min_key && new_max_key < self.offset + self.length() {
@@ -85,12 +168,19 @@ impl Store {
             // Grow bins
             let new_length = self.get_new_length(new_min_key, new_max_key);
             if new_length > self.length() as usize {
-                self.bins.resize(new_length, 0);
+                self.grow(new_length);
             }
             self.adjust(new_min_key, new_max_key);
         }
     }
 
+    // Grow the bins to `len`. `Vec::resize` alone may allocate up to twice that, so the capacity
+    // is reserved exactly to keep the memory used within the bin limit.
+    fn grow(&mut self, len: usize) {
+        self.bins.reserve_exact(len - self.bins.len());
+        self.bins.resize(len, 0);
+    }
+
     fn get_new_length(&self, new_min_key: i32, new_max_key: i32) -> usize {
         let desired_length = new_max_key - new_min_key + 1;
         usize::min(
@@ -111,17 +201,14 @@ impl Store {
                 self.bins[0] = self.count;
             } else {
                 let shift = self.offset - new_min_key;
-                if shift < 0 {
+                // Nothing to collapse when growing towards lower keys too
+                if shift < 0 && new_min_key > self.min_key {
                     let collapse_start_index = (self.min_key - self.offset) as usize;
                     let collapse_end_index = (new_min_key - self.offset) as usize;
                     let collapsed_count: u64 = self.bins[collapse_start_index..collapse_end_index]
                         .iter()
                         .sum();
-                    let zero_len = (new_min_key - self.min_key) as usize;
-                    self.bins.splice(
-                        collapse_start_index..collapse_end_index,
-                        std::iter::repeat(0).take(zero_len),
-                    );
+                    self.bins[collapse_start_index..collapse_end_index].fill(0);
                     self.bins[collapse_end_index] += collapsed_count;
                 }
                 self.min_key = new_min_key;
@@ -145,7 +232,7 @@ impl Store {
                 self.bins[idx] = 0;
             }
         } else {
-            let shift = shift.abs() as usize;
+            let shift = shift.unsigned_abs() as usize;
             for idx in 0..shift {
                 self.bins[idx] = 0;
             }
@@ -162,21 +249,151 @@ impl Store {
     }
 
     pub fn key_at_rank(&self, rank: u64) -> i32 {
+        // Ranks count from the lowest key, so from the highest key of the bins when negated
+        let rank = match self.direction {
+            CollapseDirection::Lowest => rank,
+            CollapseDirection::Highest => self.count.saturating_sub(rank + 1),
+        };
+
         let mut n = 0;
         for (i, bin) in self.bins.iter().enumerate() {
             n += *bin;
             if n > rank {
-                return i as i32 + self.offset;
+                return self.bin_key(i as i32 + self.offset);
             }
         }
 
-        self.max_key
+        self.bin_key(self.max_key)
     }
 
     pub fn count(&self) -> u64 {
         self.count
     }
 
+    /// Return the lowest and highest keys holding samples, or None if the store is empty.
+    pub fn key_range(&self) -> Option<(i32, i32)> {
+        let (min_key, max_key) = self.bin_key_range()?;
+        match self.direction {
+            CollapseDirection::Lowest => Some((min_key, max_key)),
+            CollapseDirection::Highest => Some((-max_key, -min_key)),
+        }
+    }
+
+    fn bin_key_range(&self) -> Option<(i32, i32)> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.min_key, self.max_key))
+        }
+    }
+
+    pub fn is_collapsed(&self) -> bool {
+        self.is_collapsed
+    }
+
+    /// Return the direction the bins collapse in and the limit they collapse at
+    pub fn limit(&self) -> (CollapseDirection, usize) {
+        (self.direction, self.bin_limit)
+    }
+
+    /// Return the count of the lowest bin, or the highest when collapsing the highest keys,
+    /// once the store has collapsed, as it holds every sample collapsed into it, or 0 if the
+    /// store never collapsed.
+    pub fn collapsed_count(&self) -> u64 {
+        if self.is_collapsed && self.count > 0 {
+            self.bins[(self.min_key - self.offset) as usize]
+        } else {
+            0
+        }
+    }
+
+    /// Return whether the sample of the given rank, counting from the lowest key, is in the bin
+    /// the store collapsed into.
+    pub fn is_collapsed_rank(&self, rank: u64) -> bool {
+        match self.direction {
+            CollapseDirection::Lowest => rank < self.collapsed_count(),
+            CollapseDirection::Highest => rank + self.collapsed_count() >= self.count,
+        }
+    }
+
+    /// Return an iterator over the keys and counts of the non-empty bins, in key order.
+    pub fn bins(&self) -> impl Iterator<Item = (i32, u64)> + '_ {
+        let len = self.bins.len();
+        (0..len)
+            .map(move |i| match self.direction {
+                CollapseDirection::Lowest => i,
+                CollapseDirection::Highest => len - 1 - i,
+            })
+            .filter(move |i| self.bins[*i] > 0)
+            .map(move |i| (self.bin_key(i as i32 + self.offset), self.bins[i]))
+    }
+
+    /// Remove the bins of keys up to `key`, returning their total count. The store is no longer
+    /// collapsed once the bin it collapsed into is removed.
+    pub fn remove_up_to(&mut self, key: i32) -> u64 {
+        match self.key_range() {
+            Some((min_key, _)) if min_key <= key => {}
+            _ => return 0,
+        }
+
+        let mut kept = Store::new(self.bin_limit).with_direction(self.direction);
+        let mut removed = 0;
+        for (k, count) in self.bins() {
+            if k <= key {
+                removed += count;
+            } else {
+                kept.add_count(k, count);
+            }
+        }
+        kept.is_collapsed =
+            self.is_collapsed && self.direction == CollapseDirection::Highest && kept.count > 0;
+        *self = kept;
+
+        removed
+    }
+
+    pub fn set_bin_limit(&mut self, bin_limit: usize) {
+        self.bin_limit = bin_limit;
+    }
+
+    /// Merge every pair of adjacent bins, moving the count of key `k` to `ceil(k / 2)`. The bins
+    /// are reallocated to exactly cover the remaining keys.
+    pub fn collapse_uniform(&mut self) {
+        self.fold(2);
+    }
+
+    /// Merge every `factor` adjacent bins, moving the count of key `k` to `ceil(k / factor)`.
+    /// The bins are reallocated to exactly cover the remaining keys.
+    pub fn fold(&mut self, factor: i32) {
+        if self.count == 0 {
+            return;
+        }
+
+        let fold = |key: i32| self.bin_key(fold_key(self.bin_key(key), factor));
+        let (min_key, max_key) = (fold(self.min_key), fold(self.max_key));
+        let mut bins = vec![0; (max_key - min_key + 1) as usize];
+        for key in self.min_key..=self.max_key {
+            bins[(fold(key) - min_key) as usize] += self.bins[(key - self.offset) as usize];
+        }
+
+        self.bins = bins;
+        self.offset = min_key;
+        self.min_key = min_key;
+        self.max_key = max_key;
+    }
+
+    /// Reallocate the bins to exactly cover the keys holding samples.
+    pub fn shrink_to_fit(&mut self) {
+        if self.count == 0 || self.length() == self.max_key - self.min_key + 1 {
+            return;
+        }
+
+        let start = (self.min_key - self.offset) as usize;
+        let end = (self.max_key - self.offset) as usize;
+        self.bins = self.bins[start..=end].to_vec();
+        self.offset = self.min_key;
+    }
+
     pub fn merge(&mut self, other: &Store) {
         if other.count == 0 {
             return;
@@ -194,7 +411,7 @@ impl Store {
         let collapse_start_index = other.min_key - other.offset;
         let mut collapse_end_index = i32::min(self.min_key, other.max_key + 1) - other.offset;
         if collapse_end_index > collapse_start_index {
-            let collapsed_count: u64 = self.bins
+            let collapsed_count: u64 = other.bins
                 [collapse_start_index as usize..collapse_end_index as usize]
                 .iter()
                 .sum();
@@ -208,6 +425,108 @@ impl Store {
         }
 
         self.count += other.count;
+        self.is_collapsed |= other.is_collapsed;
+    }
+
+    /// Merge all the stores in a single pass, growing the bins once to cover their combined key
+    /// range. The result matches merging them one at a time.
+    pub fn merge_all(&mut self, others: &[&Store]) {
+        let range = others.iter().filter_map(|o| o.bin_key_range()).fold(
+            None,
+            |range: Option<(i32, i32)>, (min_key, max_key)| {
+                Some(range.map_or((min_key, max_key), |(lo, hi)| {
+                    (i32::min(lo, min_key), i32::max(hi, max_key))
+                }))
+            },
+        );
+        let (min_key, max_key) = match range {
+            Some(range) => range,
+            None => return,
+        };
+
+        if self.count == 0 {
+            self.bins.clear();
+            self.extend_range(min_key, Some(max_key));
+        } else if min_key < self.min_key || max_key > self.max_key {
+            self.extend_range(min_key, Some(max_key));
+        }
+
+        for o in others.iter().filter(|o| o.count > 0) {
+            for key in o.min_key..=o.max_key {
+                // Keys below the range kept by the bins are collapsed into the lowest one
+                let idx = (i32::max(key, self.min_key) - self.offset) as usize;
+                self.bins[idx] += o.bins[(key - o.offset) as usize];
+            }
+            self.count += o.count;
+            self.is_collapsed |= o.is_collapsed;
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        encoding::put_u64(buf, self.bin_limit as u64);
+        encoding::put_u8(buf, self.is_collapsed as u8);
+        encoding::put_u8(buf, self.direction as u8);
+
+        match self.bin_key_range() {
+            Some((min_key, max_key)) => {
+                let start = (min_key - self.offset) as usize;
+                let end = (max_key - self.offset) as usize;
+                encoding::put_i32(buf, min_key);
+                encoding::put_u32(buf, (end - start + 1) as u32);
+                for count in &self.bins[start..=end] {
+                    encoding::put_u64(buf, *count);
+                }
+            }
+            None => {
+                encoding::put_i32(buf, 0);
+                encoding::put_u32(buf, 0);
+            }
+        }
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, DDSketchError> {
+        let bin_limit = usize::try_from(r.u64()?).map_err(|_| DDSketchError::Decode)?;
+        if bin_limit == 0 {
+            return Err(DDSketchError::Decode);
+        }
+        let mut store = Store::new(bin_limit);
+        store.is_collapsed = r.bool()?;
+        store.direction = match r.u8()? {
+            0 => CollapseDirection::Lowest,
+            1 => CollapseDirection::Highest,
+            _ => return Err(DDSketchError::Decode),
+        };
+
+        let min_key = r.i32()?;
+        let len = r.u32()?;
+        if len == 0 {
+            return Ok(store);
+        }
+        if len as usize > bin_limit {
+            return Err(DDSketchError::Decode);
+        }
+
+        let max_key =
+            i32::try_from(min_key as i64 + len as i64 - 1).map_err(|_| DDSketchError::Decode)?;
+        if min_key < -MAX_KEY || max_key > MAX_KEY {
+            return Err(DDSketchError::Decode);
+        }
+        let bins = r.u64_vec(len as usize)?;
+        store.count = bins
+            .iter()
+            .try_fold(0u64, |sum, count| sum.checked_add(*count))
+            .ok_or(DDSketchError::Decode)?;
+        // Encoded bins span the non-empty keys exactly
+        if bins[0] == 0 || bins[bins.len() - 1] == 0 {
+            return Err(DDSketchError::Decode);
+        }
+
+        store.bins = bins;
+        store.min_key = min_key;
+        store.max_key = max_key;
+        store.offset = min_key;
+
+        Ok(store)
     }
 
     fn copy(&mut self, o: &Store) {
@@ -216,21 +535,25 @@ impl Store {
         self.min_key = o.min_key;
         self.max_key = o.max_key;
         self.offset = o.offset;
-        self.bin_limit = o.bin_limit;
         self.is_collapsed = o.is_collapsed;
+
+        if self.length() as usize > self.bin_limit {
+            self.shrink_to_fit();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::store::Store;
+    use crate::encoding::{self, Reader};
+    use crate::store::{CollapseDirection, Store};
 
     #[test]
     fn test_simple_store() {
         let mut s = Store::new(2048);
 
         for i in 0..2048 {
-            s.add(i);
+            s.add_count(i, 1);
         }
     }
 
@@ -238,8 +561,104 @@ mod tests {
     fn test_simple_store_rev() {
         let mut s = Store::new(2048);
 
-        for i in 2048..0 {
-            s.add(i);
+        for i in (0..2048).rev() {
+            s.add_count(i, 1);
+        }
+    }
+
+    #[test]
+    fn test_collapsing_highest() {
+        let mut highest = Store::new(128).with_direction(CollapseDirection::Highest);
+        let mut lowest = Store::new(128);
+        for key in 0..1000 {
+            highest.add_count(key, 1);
+            lowest.add_count(-key, 1);
+        }
+
+        assert!(highest.is_collapsed());
+        assert_eq!(highest.key_range(), Some((0, 127)));
+        let mut mirrored: Vec<(i32, u64)> = lowest.bins().map(|(k, n)| (-k, n)).collect();
+        mirrored.reverse();
+        assert!(highest.bins().eq(mirrored));
+        assert_eq!(highest.collapsed_count(), 873);
+        assert_eq!(highest.key_at_rank(0), 0);
+        assert_eq!(highest.key_at_rank(999), 127);
+        assert!(!highest.is_collapsed_rank(126));
+        assert!(highest.is_collapsed_rank(127));
+
+        let mut keys = Store::new(128).with_direction(CollapseDirection::Highest);
+        keys.add_keys(&(0..1000).collect::<Vec<i32>>());
+        assert!(keys.bins().eq(highest.bins()));
+
+        assert_eq!(highest.remove_up_to(9), 10);
+        assert_eq!(highest.key_range(), Some((10, 127)));
+        assert_eq!(highest.collapsed_count(), 873);
+    }
+
+    #[test]
+    fn test_grow_exact() {
+        let mut s = Store::new(300);
+        for key in 0..1000 {
+            s.add_count(key, 1);
+            assert_eq!(s.bins.capacity(), s.bins.len());
+        }
+        assert_eq!(s.length(), 300);
+    }
+
+    #[test]
+    fn test_grow_both_ends() {
+        let mut s = Store::new(128);
+        for key in 100..=150 {
+            s.add_count(key, 1);
+        }
+
+        // Past the bin limit while also extending below the lowest key
+        s.add_keys(&[0, 200]);
+        assert!(s.is_collapsed());
+        assert_eq!(s.count(), 53);
+        assert_eq!(s.key_range(), Some((73, 200)));
+        assert_eq!(s.collapsed_count(), 1);
+        assert_eq!(s.bins().map(|(_, n)| n).sum::<u64>(), 53);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let encode = |bin_limit: u64, min_key: i32, counts: &[u64]| {
+            let mut buf = Vec::new();
+            encoding::put_u64(&mut buf, bin_limit);
+            encoding::put_u8(&mut buf, 0);
+            encoding::put_u8(&mut buf, 0);
+            encoding::put_i32(&mut buf, min_key);
+            encoding::put_u32(&mut buf, counts.len() as u32);
+            for count in counts {
+                encoding::put_u64(&mut buf, *count);
+            }
+            buf
+        };
+        let decode = |buf: &[u8]| Store::decode(&mut Reader::new(buf));
+
+        let store = decode(&encode(4, 10, &[1, 0, 2])).unwrap();
+        assert_eq!(store.key_range(), Some((10, 12)));
+        assert_eq!(store.count(), 3);
+        assert!(decode(&encode(4, 0, &[])).unwrap().is_empty());
+
+        assert!(decode(&encode(0, 0, &[])).is_err());
+        assert!(decode(&encode(2, 10, &[1, 0, 2])).is_err());
+        assert!(decode(&encode(4, 10, &[0, 1])).is_err());
+        assert!(decode(&encode(4, 10, &[1, 0])).is_err());
+        assert!(decode(&encode(4, i32::MAX, &[1, 1])).is_err());
+        assert!(decode(&encode(4, i32::MIN, &[1])).is_err());
+        assert!(decode(&encode(4, 10, &[u64::MAX, 1])).is_err());
+
+        let mut buf = encode(4, 10, &[1, 0, 2]);
+        buf[8] = 2;
+        assert!(decode(&buf).is_err());
+        buf[8] = 0;
+        buf[9] = 2;
+        assert!(decode(&buf).is_err());
+        buf[9] = 0;
+        for len in 0..buf.len() {
+            assert!(decode(&buf[..len]).is_err());
         }
     }
 }
@@ -0,0 +1,84 @@
+//! Fixtures shared by the unit tests
+
+use alloc::vec::Vec;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Config, DDSketch, DDSketchError, QuantileMethod};
+
+/// The configs sketches are tested with: the default, one small enough to collapse its lowest
+/// bins, and one collapsing uniformly.
+pub(crate) fn configs() -> [Config; 3] {
+    [
+        Config::defaults(),
+        Config::new(0.01, 128, 1e-9),
+        Config::uniform_collapsing(0.01, 128, 1e-9),
+    ]
+}
+
+/// Return 1000 samples, mostly positive, with every tenth 0.0. The RNG is seeded, so failures
+/// reproduce.
+pub(crate) fn random_values(seed: u64) -> Vec<f64> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..1000)
+        .map(|i| {
+            let v = rng.gen_range(-1e3..1e5);
+            if i % 10 == 0 {
+                0.0
+            } else {
+                v
+            }
+        })
+        .collect()
+}
+
+/// Return a sketch of each of `configs` holding the `random_values` of `seed`
+pub(crate) fn random_sketches(seed: u64) -> Vec<DDSketch> {
+    let values = random_values(seed);
+    configs()
+        .iter()
+        .map(|config| {
+            let mut dd = DDSketch::new(*config);
+            for v in &values {
+                dd.add(*v);
+            }
+            dd
+        })
+        .collect()
+}
+
+/// Return a sketch of the default config holding `values`
+pub(crate) fn sketch(values: &[f64]) -> DDSketch {
+    let mut dd = DDSketch::default();
+    for v in values {
+        dd.add(*v);
+    }
+    dd
+}
+
+/// Assert that both quantile functions agree for every method, at each percentile strictly
+/// between 0 and 1.
+pub(crate) fn assert_same_quantiles<A, B>(a: A, b: B)
+where
+    A: Fn(f64, QuantileMethod) -> Result<Option<f64>, DDSketchError>,
+    B: Fn(f64, QuantileMethod) -> Result<Option<f64>, DDSketchError>,
+{
+    for i in 1..100 {
+        let q = i as f64 / 100.0;
+        for method in [
+            QuantileMethod::Lower,
+            QuantileMethod::Upper,
+            QuantileMethod::Nearest,
+            QuantileMethod::Linear,
+        ] {
+            assert_eq!(
+                a(q, method).unwrap(),
+                b(q, method).unwrap(),
+                "q={} {:?}",
+                q,
+                method
+            );
+        }
+    }
+}
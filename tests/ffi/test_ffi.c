@@ -0,0 +1,67 @@
+/*
+ * Calls every function of include/ddsketch.h, to check the header against the library built
+ * from src/ffi.rs. Built and run by CI, see .github/workflows/main.yml:
+ *
+ *   cargo rustc --features ffi --crate-type staticlib
+ *   cc -Wall -Wextra -Werror -Iinclude tests/ffi/test_ffi.c target/debug/libsketches_ddsketch.a \
+ *     -lpthread -ldl -lm -o target/test_ffi
+ *   target/test_ffi
+ */
+
+#include <math.h>
+#include <stdio.h>
+
+#include "ddsketch.h"
+
+#define CHECK(cond)                                                               \
+  do {                                                                            \
+    if (!(cond)) {                                                                \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond);  \
+      return 1;                                                                   \
+    }                                                                             \
+  } while (0)
+
+int main(void) {
+  DDSketch *sketch = ddsketch_new(0.01, 2048, 1e-9);
+  CHECK(sketch != NULL);
+  CHECK(ddsketch_new(2.0, 2048, 1e-9) == NULL);
+
+  double q = 0.0;
+  CHECK(ddsketch_quantile(sketch, 0.5, &q) == DD_SKETCH_STATUS_EMPTY);
+  CHECK(ddsketch_add(sketch, 1.0) == DD_SKETCH_STATUS_OK);
+  CHECK(ddsketch_add_with_count(sketch, 2.0, 3) == DD_SKETCH_STATUS_OK);
+  CHECK(ddsketch_add(NULL, 1.0) == DD_SKETCH_STATUS_NULL_POINTER);
+  CHECK(ddsketch_count(sketch) == 4);
+
+  CHECK(ddsketch_quantile(sketch, 0.5, &q) == DD_SKETCH_STATUS_OK);
+  CHECK(fabs(q - 2.0) < 0.02);
+  CHECK(ddsketch_quantile(sketch, 1.5, &q) == DD_SKETCH_STATUS_INVALID_QUANTILE);
+
+  DDSketch *other = ddsketch_new_default();
+  CHECK(other != NULL);
+  CHECK(ddsketch_add(other, 3.0) == DD_SKETCH_STATUS_OK);
+  CHECK(ddsketch_merge(sketch, other) == DD_SKETCH_STATUS_OK);
+  CHECK(ddsketch_merge(sketch, sketch) == DD_SKETCH_STATUS_SAME_SKETCH);
+  CHECK(ddsketch_count(sketch) == 5);
+
+  size_t len = 0;
+  CHECK(ddsketch_encode(sketch, NULL, 0, &len) == DD_SKETCH_STATUS_BUFFER_TOO_SMALL);
+  CHECK(len > 0);
+  uint8_t *buf = malloc(len);
+  CHECK(buf != NULL);
+  CHECK(ddsketch_encode(sketch, buf, len, &len) == DD_SKETCH_STATUS_OK);
+
+  DDSketch *decoded = NULL;
+  CHECK(ddsketch_decode(buf, len, &decoded) == DD_SKETCH_STATUS_OK);
+  CHECK(ddsketch_count(decoded) == 5);
+  CHECK(ddsketch_decode(buf, len - 1, &decoded) == DD_SKETCH_STATUS_INVALID_ENCODING);
+
+  free(buf);
+  ddsketch_free(decoded);
+  ddsketch_free(other);
+  ddsketch_free(sketch);
+  ddsketch_free(NULL);
+
+  printf("ffi ok\n");
+  return 0;
+}
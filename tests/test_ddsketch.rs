@@ -7,6 +7,7 @@ use std::time::Instant;
 
 use sketches_ddsketch::Config;
 use sketches_ddsketch::DDSketch;
+use sketches_ddsketch::KeyMapping;
 
 const TEST_ALPHA: f64 = 0.01;
 const TEST_MAX_BINS: u32 = 1024;
@@ -81,23 +82,25 @@ fn evaluate_sketch(count: usize, generator: &mut Box<dyn generator::Generator>)
 }
 
 fn compare_sketches(d: &mut Dataset, g: &DDSketch) {
+    compare_sketches_with_alpha(d, g, TEST_ALPHA);
+}
+
+fn compare_sketches_with_alpha(d: &mut Dataset, g: &DDSketch, alpha: f64) {
     for q in &TEST_QUANTILES {
         let lower = d.lower_quantile(*q);
         let upper = d.upper_quantile(*q);
 
-        let min_expected;
-        if lower < 0.0 {
-            min_expected = lower * (1.0 + TEST_ALPHA);
+        let min_expected = if lower < 0.0 {
+            lower * (1.0 + alpha)
         } else {
-            min_expected = lower * (1.0 - TEST_ALPHA);
-        }
+            lower * (1.0 - alpha)
+        };
 
-        let max_expected;
-        if upper > 0.0 {
-            max_expected = upper * (1.0 + TEST_ALPHA);
+        let max_expected = if upper > 0.0 {
+            upper * (1.0 + alpha)
         } else {
-            max_expected = upper * (1.0 - TEST_ALPHA);
-        }
+            upper * (1.0 - alpha)
+        };
 
         let quantile = g.quantile(*q).unwrap().unwrap();
 
@@ -223,6 +226,60 @@ fn test_merge_mixed() {
     })
 }
 
+#[test]
+fn test_uniform_collapsing() {
+    let c = Config::uniform_collapsing(TEST_ALPHA, 128, TEST_MIN_VALUE);
+    let mut d = Dataset::new();
+    let mut g1 = DDSketch::new(c);
+    let mut g2 = DDSketch::new(c);
+
+    let mut generator1 = generator::Lognormal::new(0.0, 2.0);
+    let mut generator2 = generator::Normal::new(-100.0, 20.0);
+    for _ in 0..1000 {
+        let value = generator1.generate();
+        g1.add(value);
+        d.add(value);
+
+        let value = generator2.generate();
+        g2.add(value);
+        d.add(value);
+    }
+    g1.merge(&g2).unwrap();
+
+    assert!(g1.alpha() > TEST_ALPHA);
+    assert!(g1.length() <= 128);
+    compare_sketches_with_alpha(&mut d, &g1, g1.alpha());
+}
+
+#[test]
+fn test_cubic_interpolated_mapping() {
+    let c = Config::new(TEST_ALPHA, TEST_MAX_BINS, TEST_MIN_VALUE)
+        .with_mapping(KeyMapping::CubicInterpolated);
+    let mut d = Dataset::new();
+    let mut g = DDSketch::new(c);
+
+    let mut generator1 = generator::Lognormal::new(0.0, 2.0);
+    let mut generator2 = generator::Normal::new(-100.0, 20.0);
+    for _ in 0..1000 {
+        let value = generator1.generate();
+        g.add(value);
+        d.add(value);
+
+        let value = generator2.generate();
+        g.add(value);
+        d.add(value);
+    }
+
+    compare_sketches(&mut d, &g);
+    assert!(g
+        .merge(&DDSketch::new(Config::new(
+            TEST_ALPHA,
+            TEST_MAX_BINS,
+            TEST_MIN_VALUE
+        )))
+        .is_err());
+}
+
 #[test]
 fn test_merge_incompatible() {
     let c1 = Config::new(TEST_ALPHA, TEST_MAX_BINS, TEST_MIN_VALUE);